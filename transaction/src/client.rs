@@ -1,29 +1,50 @@
-use serde::{Serialize, Serializer, ser::SerializeStruct};
+use crate::{FraudDecision, Money};
+use serde::{Serialize, Serializer, ser::Error, ser::SerializeStruct};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientAccount {
     // The "client" field
     pub client_id: u16,
 
     // Amount available in this client account
-    pub available: f64,
+    pub available: Money,
 
     // Amount held from disputes
-    pub held: f64,
+    pub held: Money,
 
     // Whether this account is locked
     pub locked: bool,
+
+    // Whether support closed this account, for good. Closed accounts
+    // are also locked, so the output doesn't need another column
+    pub closed: bool,
+
+    // Fraud rule decisions taken against this account, whatever their
+    // action, oldest first
+    pub fraud_decisions: Vec<FraudDecision>,
 }
 
 impl ClientAccount {
     pub fn new(client_id: u16) -> Self {
         ClientAccount {
             client_id,
-            available: 0.0,
-            held: 0.0,
+            available: Money::ZERO,
+            held: Money::ZERO,
             locked: false,
+            closed: false,
+            fraud_decisions: Vec::new(),
         }
     }
+
+    // Available plus held funds, None if that doesn't fit
+    pub fn total(&self) -> Option<Money> {
+        self.available.checked_add(self.held)
+    }
+
+    // How many times fraud rules flagged this account
+    pub fn fraud_flags(&self) -> u32 {
+        u32::try_from(self.fraud_decisions.len()).unwrap_or(u32::MAX)
+    }
 }
 
 impl Serialize for ClientAccount {
@@ -31,16 +52,20 @@ impl Serialize for ClientAccount {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("ClientAccount", 5)?;
+        let mut state = serializer.serialize_struct("ClientAccount", 7)?;
         state.serialize_field("client", &self.client_id)?;
 
-        state.serialize_field("available", &format!("{:.4}", self.available))?;
-        state.serialize_field("held", &format!("{:.4}", self.held))?;
+        state.serialize_field("available", &self.available)?;
+        state.serialize_field("held", &self.held)?;
 
-        let total = self.available + self.held;
-        state.serialize_field("total", &format!("{:.4}", total))?;
+        let total = self
+            .total()
+            .ok_or_else(|| S::Error::custom("account total overflows"))?;
+        state.serialize_field("total", &total)?;
 
         state.serialize_field("locked", &self.locked)?;
+        state.serialize_field("fraud_flags", &self.fraud_flags())?;
+        state.serialize_field("fraud_decisions", &self.fraud_decisions)?;
         state.end()
     }
 }
@@ -0,0 +1,637 @@
+use crate::storage::{AccountRepository, LedgerRepository, StorageError};
+use crate::{
+    ClientAccount, DisputeState, FraudAction, FraudDecision, InputPosition, LedgerEntry, Money,
+    Transaction,
+};
+
+use redb::{Database, ReadableTable, Table, TableDefinition, WriteTransaction};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Records are stored as fixed width little endian byte arrays,
+// see the encode/decode functions below for the layouts. The
+// administrative operations carry text so get a table of their own, as
+// do the fraud decisions against accounts, keyed by client and the
+// decision's index on the account.
+const ACCOUNTS: TableDefinition<u16, [u8; ACCOUNT_SIZE]> = TableDefinition::new("accounts");
+const FRAUD_DECISIONS: TableDefinition<(u16, u32), &[u8]> = TableDefinition::new("fraud_decisions");
+const LEDGER: TableDefinition<u64, [u8; ENTRY_SIZE]> = TableDefinition::new("ledger");
+const ADMIN: TableDefinition<u64, &[u8]> = TableDefinition::new("admin");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+const META_TEXT: TableDefinition<&str, &str> = TableDefinition::new("meta_text");
+
+// The checkpoint's record count, and the line and source of that record
+const CHECKPOINT_KEY: &str = "checkpoint";
+const CHECKPOINT_LINE_KEY: &str = "checkpoint_line";
+const CHECKPOINT_SOURCE_KEY: &str = "checkpoint_source";
+
+const ACCOUNT_SIZE: usize = 17;
+const ENTRY_SIZE: usize = 12;
+
+impl From<redb::Error> for StorageError {
+    fn from(err: redb::Error) -> Self {
+        StorageError::new(err)
+    }
+}
+
+// Shorthand for the many specific redb error types
+fn storage_err(err: impl Into<redb::Error>) -> StorageError {
+    StorageError::from(err.into())
+}
+
+/// Embedded on-disk storage backed by a redb database file.
+///
+/// All writes go into one open write transaction, so they are only
+/// durable once `commit` is called. A crash in between rolls the
+/// file back to the previous commit and its checkpoint.
+///
+/// Clones share the same database and open transaction, which is how
+/// one file backs both the accounts and the ledger of an engine.
+#[derive(Clone)]
+pub struct DiskStorage {
+    inner: Arc<Mutex<DiskInner>>,
+}
+
+struct DiskInner {
+    // Declared first so the transaction is dropped before the database
+    txn: Option<WriteTransaction>,
+    db: Database,
+
+    // Lets the second of the account and ledger commits be skipped
+    dirty: bool,
+    committed: InputPosition,
+}
+
+impl DiskStorage {
+    /// Opens the database at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let db = Database::create(path).map_err(storage_err)?;
+
+        // Make sure the tables exist so reads never fail on a new file
+        let txn = db.begin_write().map_err(storage_err)?;
+        txn.open_table(ACCOUNTS).map_err(storage_err)?;
+        txn.open_table(FRAUD_DECISIONS).map_err(storage_err)?;
+        txn.open_table(LEDGER).map_err(storage_err)?;
+        txn.open_table(ADMIN).map_err(storage_err)?;
+        txn.open_table(META_TEXT).map_err(storage_err)?;
+        let committed = {
+            let meta = txn.open_table(META).map_err(storage_err)?;
+            let number = |key| -> Result<u64, StorageError> {
+                let value = meta.get(key).map_err(storage_err)?;
+                Ok(value.map(|v| v.value()).unwrap_or(0))
+            };
+            let text = txn.open_table(META_TEXT).map_err(storage_err)?;
+            let source = text.get(CHECKPOINT_SOURCE_KEY).map_err(storage_err)?;
+            InputPosition {
+                record: number(CHECKPOINT_KEY)?,
+                source: source.map(|s| s.value().to_string()),
+                line: number(CHECKPOINT_LINE_KEY)?,
+            }
+        };
+
+        let inner = DiskInner {
+            txn: Some(txn),
+            db,
+            dirty: false,
+            committed,
+        };
+        Ok(DiskStorage {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, DiskInner>, StorageError> {
+        self.inner
+            .lock()
+            .map_err(|_| StorageError::new("disk storage lock poisoned"))
+    }
+
+    fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        Ok(self.lock()?.committed.clone())
+    }
+
+    fn commit(&self, checkpoint: &InputPosition) -> Result<(), StorageError> {
+        let mut inner = self.lock()?;
+        if !inner.dirty && inner.committed == *checkpoint {
+            return Ok(());
+        }
+
+        {
+            let mut table = inner.txn()?.open_table(META).map_err(storage_err)?;
+            table
+                .insert(CHECKPOINT_KEY, checkpoint.record)
+                .map_err(storage_err)?;
+            table
+                .insert(CHECKPOINT_LINE_KEY, checkpoint.line)
+                .map_err(storage_err)?;
+
+            let mut table = inner.txn()?.open_table(META_TEXT).map_err(storage_err)?;
+            match &checkpoint.source {
+                Some(source) => table.insert(CHECKPOINT_SOURCE_KEY, source.as_str()),
+                None => table.remove(CHECKPOINT_SOURCE_KEY),
+            }
+            .map_err(storage_err)?;
+        }
+
+        if let Some(txn) = inner.txn.take() {
+            txn.commit().map_err(storage_err)?;
+        }
+        inner.txn = Some(inner.db.begin_write().map_err(storage_err)?);
+        inner.dirty = false;
+        inner.committed = checkpoint.clone();
+        Ok(())
+    }
+}
+
+impl DiskInner {
+    fn txn(&self) -> Result<&WriteTransaction, StorageError> {
+        // Only left empty by a commit that failed, which may have lost
+        // the writes since the last one
+        self.txn
+            .as_ref()
+            .ok_or_else(|| StorageError::new("no open write transaction after a failed commit"))
+    }
+}
+
+impl AccountRepository for DiskStorage {
+    fn find(&self, client_id: u16) -> Result<Option<ClientAccount>, StorageError> {
+        let inner = self.lock()?;
+        let table = inner.txn()?.open_table(ACCOUNTS).map_err(storage_err)?;
+        let Some(record) = table.get(client_id).map_err(storage_err)? else {
+            return Ok(None);
+        };
+
+        let mut account = decode_account(client_id, record.value());
+        let decisions = inner
+            .txn()?
+            .open_table(FRAUD_DECISIONS)
+            .map_err(storage_err)?;
+        account.fraud_decisions = load_decisions(&decisions, client_id)?;
+        Ok(Some(account))
+    }
+
+    fn save(&mut self, account: ClientAccount) -> Result<(), StorageError> {
+        let mut inner = self.lock()?;
+        inner.dirty = true;
+        let mut table = inner.txn()?.open_table(ACCOUNTS).map_err(storage_err)?;
+        table
+            .insert(account.client_id, encode_account(&account))
+            .map_err(storage_err)?;
+
+        // Decisions are only ever appended, so writing back from the
+        // newest until one is already stored covers the new ones
+        let mut decisions = inner
+            .txn()?
+            .open_table(FRAUD_DECISIONS)
+            .map_err(storage_err)?;
+        for (index, decision) in account.fraud_decisions.iter().enumerate().rev() {
+            let index = u32::try_from(index)
+                .map_err(|_| StorageError::new("too many fraud decisions to store"))?;
+            let key = (account.client_id, index);
+            if decisions.get(key).map_err(storage_err)?.is_some() {
+                break;
+            }
+            decisions
+                .insert(key, encode_decision(decision).as_slice())
+                .map_err(storage_err)?;
+        }
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<ClientAccount>, StorageError> {
+        let inner = self.lock()?;
+        let table = inner.txn()?.open_table(ACCOUNTS).map_err(storage_err)?;
+        let decisions = inner
+            .txn()?
+            .open_table(FRAUD_DECISIONS)
+            .map_err(storage_err)?;
+        table
+            .iter()
+            .map_err(storage_err)?
+            .map(|record| {
+                let (client_id, account) = record.map_err(storage_err)?;
+                let mut account = decode_account(client_id.value(), account.value());
+                account.fraud_decisions = load_decisions(&decisions, account.client_id)?;
+                Ok(account)
+            })
+            .collect()
+    }
+
+    fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        DiskStorage::checkpoint(self)
+    }
+
+    fn commit(&mut self, checkpoint: &InputPosition) -> Result<(), StorageError> {
+        DiskStorage::commit(self, checkpoint)
+    }
+}
+
+impl LedgerRepository for DiskStorage {
+    fn find(&self, transaction_id: u64) -> Result<Option<LedgerEntry>, StorageError> {
+        let inner = self.lock()?;
+        let table = inner.txn()?.open_table(LEDGER).map_err(storage_err)?;
+        if let Some(record) = table.get(transaction_id).map_err(storage_err)? {
+            return decode_entry(transaction_id, record.value()).map(Some);
+        }
+
+        let table = inner.txn()?.open_table(ADMIN).map_err(storage_err)?;
+        let record = table.get(transaction_id).map_err(storage_err)?;
+        record
+            .map(|r| decode_admin_entry(transaction_id, r.value()))
+            .transpose()
+    }
+
+    fn save(&mut self, transaction_id: u64, entry: LedgerEntry) -> Result<(), StorageError> {
+        let mut inner = self.lock()?;
+        inner.dirty = true;
+        if entry.transaction.is_admin() {
+            let mut table = inner.txn()?.open_table(ADMIN).map_err(storage_err)?;
+            table
+                .insert(transaction_id, encode_admin_entry(&entry)?.as_slice())
+                .map_err(storage_err)?;
+            return Ok(());
+        }
+
+        let mut table = inner.txn()?.open_table(LEDGER).map_err(storage_err)?;
+        table
+            .insert(transaction_id, encode_entry(&entry)?)
+            .map_err(storage_err)?;
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<(u64, LedgerEntry)>, StorageError> {
+        let inner = self.lock()?;
+        let table = inner.txn()?.open_table(LEDGER).map_err(storage_err)?;
+        let mut entries = table
+            .iter()
+            .map_err(storage_err)?
+            .map(|record| {
+                let (transaction_id, entry) = record.map_err(storage_err)?;
+                let transaction_id = transaction_id.value();
+                Ok((transaction_id, decode_entry(transaction_id, entry.value())?))
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+
+        let table = inner.txn()?.open_table(ADMIN).map_err(storage_err)?;
+        for record in table.iter().map_err(storage_err)? {
+            let (transaction_id, entry) = record.map_err(storage_err)?;
+            let transaction_id = transaction_id.value();
+            entries.push((
+                transaction_id,
+                decode_admin_entry(transaction_id, entry.value())?,
+            ));
+        }
+        Ok(entries)
+    }
+
+    fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        DiskStorage::checkpoint(self)
+    }
+
+    fn commit(&mut self, checkpoint: &InputPosition) -> Result<(), StorageError> {
+        DiskStorage::commit(self, checkpoint)
+    }
+}
+
+// Layout: available (i64), held (i64), flags (u8) with locked in the
+// lowest bit and closed in the next
+fn encode_account(account: &ClientAccount) -> [u8; ACCOUNT_SIZE] {
+    let mut record = [0; ACCOUNT_SIZE];
+    record[0..8].copy_from_slice(&account.available.raw().to_le_bytes());
+    record[8..16].copy_from_slice(&account.held.raw().to_le_bytes());
+    record[16] = account.locked as u8 | (account.closed as u8) << 1;
+    record
+}
+
+fn decode_account(client_id: u16, record: [u8; ACCOUNT_SIZE]) -> ClientAccount {
+    let money = |bytes: &[u8]| Money::from_raw(i64::from_le_bytes(bytes.try_into().unwrap()));
+    ClientAccount {
+        client_id,
+        available: money(&record[0..8]),
+        held: money(&record[8..16]),
+        locked: record[16] & 1 != 0,
+        closed: record[16] & 2 != 0,
+        fraud_decisions: Vec::new(),
+    }
+}
+
+fn load_decisions(
+    table: &Table<(u16, u32), &[u8]>,
+    client_id: u16,
+) -> Result<Vec<FraudDecision>, StorageError> {
+    table
+        .range((client_id, 0)..=(client_id, u32::MAX))
+        .map_err(storage_err)?
+        .map(|record| {
+            let (_, decision) = record.map_err(storage_err)?;
+            decode_decision(client_id, decision.value())
+        })
+        .collect()
+}
+
+// Layout: action (u8), transaction (u64), rule. The client is the key's.
+fn encode_decision(decision: &FraudDecision) -> Vec<u8> {
+    let action = match decision.action {
+        FraudAction::Flag => 0,
+        FraudAction::Reject => 1,
+        FraudAction::Lock => 2,
+    };
+
+    let mut record = Vec::with_capacity(9 + decision.rule.len());
+    record.push(action);
+    record.extend_from_slice(&decision.transaction_id.to_le_bytes());
+    record.extend_from_slice(decision.rule.as_bytes());
+    record
+}
+
+fn decode_decision(client_id: u16, record: &[u8]) -> Result<FraudDecision, StorageError> {
+    let corrupt = || {
+        StorageError::new(format!(
+            "fraud decision for client {} is corrupt",
+            client_id
+        ))
+    };
+    if record.len() < 9 {
+        return Err(corrupt());
+    }
+
+    let action = match record[0] {
+        0 => FraudAction::Flag,
+        1 => FraudAction::Reject,
+        2 => FraudAction::Lock,
+        _ => return Err(corrupt()),
+    };
+    let rule = String::from_utf8(record[9..].to_vec()).map_err(|_| corrupt())?;
+
+    Ok(FraudDecision {
+        rule,
+        action,
+        client_id,
+        transaction_id: u64::from_le_bytes(record[1..9].try_into().unwrap()),
+    })
+}
+
+// Layout: kind (u8), client (u16), amount (i64), dispute state (u8)
+fn encode_entry(entry: &LedgerEntry) -> Result<[u8; ENTRY_SIZE], StorageError> {
+    let (kind, client_id, amount) = match &entry.transaction {
+        Transaction::Deposit {
+            client_id, amount, ..
+        } => (0, *client_id, *amount),
+        Transaction::Withdraw {
+            client_id, amount, ..
+        } => (1, *client_id, *amount),
+        other => {
+            return Err(StorageError::new(format!(
+                "{} can't be stored in the ledger",
+                other
+            )));
+        }
+    };
+
+    let state = match entry.state {
+        DisputeState::Settled => 0,
+        DisputeState::Disputed => 1,
+        DisputeState::Resolved => 2,
+        DisputeState::ChargedBack => 3,
+    };
+
+    let mut record = [0; ENTRY_SIZE];
+    record[0] = kind;
+    record[1..3].copy_from_slice(&client_id.to_le_bytes());
+    record[3..11].copy_from_slice(&amount.raw().to_le_bytes());
+    record[11] = state;
+    Ok(record)
+}
+
+fn decode_entry(
+    transaction_id: u64,
+    record: [u8; ENTRY_SIZE],
+) -> Result<LedgerEntry, StorageError> {
+    let client_id = u16::from_le_bytes([record[1], record[2]]);
+    let amount = Money::from_raw(i64::from_le_bytes(record[3..11].try_into().unwrap()));
+    let corrupt = || StorageError::new(format!("ledger entry {} is corrupt", transaction_id));
+
+    let transaction = match record[0] {
+        0 => Transaction::Deposit {
+            transaction_id,
+            client_id,
+            amount,
+        },
+        1 => Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount,
+        },
+        _ => return Err(corrupt()),
+    };
+
+    let state = match record[11] {
+        0 => DisputeState::Settled,
+        1 => DisputeState::Disputed,
+        2 => DisputeState::Resolved,
+        3 => DisputeState::ChargedBack,
+        _ => return Err(corrupt()),
+    };
+
+    Ok(LedgerEntry { transaction, state })
+}
+
+// Layout: kind (u8), client (u16), operator length (u32), operator,
+// reason. Administrative operations can't be disputed so they're
+// always settled.
+fn encode_admin_entry(entry: &LedgerEntry) -> Result<Vec<u8>, StorageError> {
+    let kind = match entry.transaction {
+        Transaction::Freeze { .. } => 0,
+        Transaction::Unfreeze { .. } => 1,
+        Transaction::Close { .. } => 2,
+        ref other => {
+            return Err(StorageError::new(format!(
+                "{} isn't an administrative operation",
+                other
+            )));
+        }
+    };
+    let (operator, reason) = entry.transaction.authorization().unwrap_or_default();
+    let operator_len = u32::try_from(operator.len())
+        .map_err(|_| StorageError::new("operator name is too long to store"))?;
+
+    let mut record = Vec::with_capacity(7 + operator.len() + reason.len());
+    record.push(kind);
+    record.extend_from_slice(&entry.transaction.client_id().to_le_bytes());
+    record.extend_from_slice(&operator_len.to_le_bytes());
+    record.extend_from_slice(operator.as_bytes());
+    record.extend_from_slice(reason.as_bytes());
+    Ok(record)
+}
+
+fn decode_admin_entry(transaction_id: u64, record: &[u8]) -> Result<LedgerEntry, StorageError> {
+    let corrupt = || StorageError::new(format!("ledger entry {} is corrupt", transaction_id));
+    if record.len() < 7 {
+        return Err(corrupt());
+    }
+
+    let client_id = u16::from_le_bytes([record[1], record[2]]);
+    let operator_len = u32::from_le_bytes(record[3..7].try_into().unwrap()) as usize;
+    let text = |bytes: Option<&[u8]>| {
+        bytes
+            .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+            .ok_or_else(corrupt)
+    };
+    let operator = text(record.get(7..7 + operator_len))?;
+    let reason = text(record.get(7 + operator_len..))?;
+
+    let transaction = match record[0] {
+        0 => Transaction::Freeze {
+            transaction_id,
+            client_id,
+            operator,
+            reason,
+        },
+        1 => Transaction::Unfreeze {
+            transaction_id,
+            client_id,
+            operator,
+            reason,
+        },
+        2 => Transaction::Close {
+            transaction_id,
+            client_id,
+            operator,
+            reason,
+        },
+        _ => return Err(corrupt()),
+    };
+
+    Ok(LedgerEntry::new(transaction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `record`th record of an input without a name
+    fn position(record: u64) -> InputPosition {
+        InputPosition {
+            record,
+            source: None,
+            line: record + 1,
+        }
+    }
+
+    #[test]
+    fn keeps_committed_records_across_reopen() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("store.redb");
+        let checkpoint = InputPosition {
+            record: 42,
+            source: Some("day1.csv".to_string()),
+            line: 43,
+        };
+
+        let mut account = ClientAccount::new(7);
+        account.available = "12.5".parse()?;
+        account.locked = true;
+
+        let entry = LedgerEntry {
+            transaction: Transaction::Withdraw {
+                transaction_id: 3,
+                client_id: 7,
+                amount: "0.0001".parse()?,
+            },
+            state: DisputeState::ChargedBack,
+        };
+
+        {
+            let mut storage = DiskStorage::open(&path)?;
+            AccountRepository::save(&mut storage, account.clone())?;
+            LedgerRepository::save(&mut storage, 3, entry.clone())?;
+            storage.commit(&checkpoint)?;
+        }
+
+        let storage = DiskStorage::open(&path)?;
+        assert_eq!(storage.checkpoint()?, checkpoint);
+        assert_eq!(AccountRepository::find(&storage, 7)?, Some(account.clone()));
+        assert_eq!(LedgerRepository::find(&storage, 3)?, Some(entry.clone()));
+        assert_eq!(AccountRepository::all(&storage)?, vec![account]);
+        assert_eq!(LedgerRepository::all(&storage)?, vec![(3, entry)]);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_admin_entries() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("store.redb");
+
+        let mut account = ClientAccount::new(2);
+        account.locked = true;
+        account.closed = true;
+        account.fraud_decisions = vec![
+            FraudDecision {
+                rule: "dispute velocity".to_string(),
+                action: FraudAction::Flag,
+                client_id: 2,
+                transaction_id: 4,
+            },
+            FraudDecision {
+                rule: "withdrawal velocity".to_string(),
+                action: FraudAction::Lock,
+                client_id: 2,
+                transaction_id: 8,
+            },
+        ];
+
+        let entry = LedgerEntry::new(Transaction::Close {
+            transaction_id: 9,
+            client_id: 2,
+            operator: "alice".to_string(),
+            reason: "requested by the client, ticket #7".to_string(),
+        });
+
+        {
+            let mut storage = DiskStorage::open(&path)?;
+            AccountRepository::save(&mut storage, account.clone())?;
+            LedgerRepository::save(&mut storage, 9, entry.clone())?;
+            storage.commit(&position(1))?;
+        }
+
+        let storage = DiskStorage::open(&path)?;
+        assert_eq!(AccountRepository::find(&storage, 2)?, Some(account));
+        assert_eq!(LedgerRepository::find(&storage, 9)?, Some(entry.clone()));
+        assert_eq!(LedgerRepository::all(&storage)?, vec![(9, entry)]);
+        Ok(())
+    }
+
+    #[test]
+    fn drops_uncommitted_records() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("store.redb");
+
+        {
+            let mut storage = DiskStorage::open(&path)?;
+            AccountRepository::save(&mut storage, ClientAccount::new(1))?;
+            storage.commit(&position(1))?;
+            AccountRepository::save(&mut storage, ClientAccount::new(2))?;
+        }
+
+        let storage = DiskStorage::open(&path)?;
+        assert_eq!(storage.checkpoint()?, position(1));
+        assert!(AccountRepository::find(&storage, 1)?.is_some());
+        assert!(AccountRepository::find(&storage, 2)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn errors_after_a_failed_commit() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut storage = DiskStorage::open(dir.path().join("store.redb"))?;
+
+        // As a commit that failed leaves it
+        storage.lock()?.txn = None;
+
+        assert!(AccountRepository::find(&storage, 1).is_err());
+        assert!(AccountRepository::save(&mut storage, ClientAccount::new(1)).is_err());
+        assert!(LedgerRepository::all(&storage).is_err());
+        assert!(storage.commit(&position(1)).is_err());
+        Ok(())
+    }
+}
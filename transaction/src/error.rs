@@ -0,0 +1,225 @@
+use crate::{DisputeState, Money, StorageError};
+use std::fmt;
+
+/// Reasons `TransactionEngine::handle` can reject a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    // A deposit or withdrawal reused an id already in the ledger
+    DuplicateTransaction {
+        transaction_id: u64,
+    },
+
+    // Deposits and withdrawals must move a positive amount
+    NegativeAmount {
+        transaction_id: u64,
+        amount: Money,
+    },
+
+    // The account was locked by a chargeback or frozen by support
+    AccountLocked {
+        client_id: u16,
+    },
+
+    // The account was closed by support and takes no more transactions
+    AccountClosed {
+        client_id: u16,
+    },
+
+    // Unfreeze of an account that isn't locked
+    NotLocked {
+        client_id: u16,
+    },
+
+    // Close of an account that still has funds held by disputes
+    FundsHeld {
+        client_id: u16,
+        held: Money,
+    },
+
+    // Administrative operation without an operator or a reason
+    MissingAuthorization {
+        transaction_id: u64,
+    },
+
+    // A fraud rule refused the transaction
+    FraudSuspected {
+        client_id: u16,
+        rule: String,
+    },
+
+    // Not enough available funds to withdraw or hold
+    InsufficientFunds {
+        client_id: u16,
+        available: Money,
+        required: Money,
+    },
+
+    // Not enough held funds to release a dispute
+    InsufficientHeldFunds {
+        client_id: u16,
+        held: Money,
+        required: Money,
+    },
+
+    // Referenced transaction is not in the ledger
+    UnknownTransaction {
+        transaction_id: u64,
+    },
+
+    // Dispute, resolve or chargeback for a client we've never seen
+    UnknownClient {
+        client_id: u16,
+    },
+
+    // Referenced transaction belongs to another client
+    ClientMismatch {
+        transaction_id: u64,
+        client_id: u16,
+        owner_id: u16,
+    },
+
+    // Resolve or chargeback of a transaction that isn't disputed
+    NotDisputed {
+        transaction_id: u64,
+    },
+
+    // Dispute of a transaction that is already disputed
+    AlreadyDisputed {
+        transaction_id: u64,
+    },
+
+    // Dispute of a transaction whose dispute already ended
+    DisputeClosed {
+        transaction_id: u64,
+        state: DisputeState,
+    },
+
+    // Applying the transaction would overflow the account balances
+    Overflow {
+        client_id: u16,
+    },
+
+    // The storage backend failed, the transaction may not be applied
+    Storage(StorageError),
+}
+
+impl EngineError {
+    /// Stable snake_case name of the rejection reason, for reports and APIs
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::DuplicateTransaction { .. } => "duplicate_transaction",
+            EngineError::NegativeAmount { .. } => "negative_amount",
+            EngineError::AccountLocked { .. } => "account_locked",
+            EngineError::AccountClosed { .. } => "account_closed",
+            EngineError::NotLocked { .. } => "not_locked",
+            EngineError::FundsHeld { .. } => "funds_held",
+            EngineError::MissingAuthorization { .. } => "missing_authorization",
+            EngineError::FraudSuspected { .. } => "fraud_suspected",
+            EngineError::InsufficientFunds { .. } => "insufficient_funds",
+            EngineError::InsufficientHeldFunds { .. } => "insufficient_held_funds",
+            EngineError::UnknownTransaction { .. } => "unknown_transaction",
+            EngineError::UnknownClient { .. } => "unknown_client",
+            EngineError::ClientMismatch { .. } => "client_mismatch",
+            EngineError::NotDisputed { .. } => "not_disputed",
+            EngineError::AlreadyDisputed { .. } => "already_disputed",
+            EngineError::DisputeClosed { .. } => "dispute_closed",
+            EngineError::Overflow { .. } => "overflow",
+            EngineError::Storage(_) => "storage_error",
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::DuplicateTransaction { transaction_id } => {
+                write!(f, "transaction {} is not unique", transaction_id)
+            }
+            EngineError::NegativeAmount {
+                transaction_id,
+                amount,
+            } => write!(
+                f,
+                "transaction {} has negative amount {}",
+                transaction_id, amount
+            ),
+            EngineError::AccountLocked { client_id } => {
+                write!(f, "client account {} is locked", client_id)
+            }
+            EngineError::AccountClosed { client_id } => {
+                write!(f, "client account {} is closed", client_id)
+            }
+            EngineError::NotLocked { client_id } => {
+                write!(f, "client account {} is not locked", client_id)
+            }
+            EngineError::FundsHeld { client_id, held } => {
+                write!(f, "client account {} still has {} held", client_id, held)
+            }
+            EngineError::MissingAuthorization { transaction_id } => write!(
+                f,
+                "transaction {} needs an operator and a reason",
+                transaction_id
+            ),
+            EngineError::FraudSuspected { client_id, rule } => write!(
+                f,
+                "fraud rule {} refused transaction for client {}",
+                rule, client_id
+            ),
+            EngineError::InsufficientFunds {
+                client_id,
+                available,
+                required,
+            } => write!(
+                f,
+                "client account {} has {} available but needs {}",
+                client_id, available, required
+            ),
+            EngineError::InsufficientHeldFunds {
+                client_id,
+                held,
+                required,
+            } => write!(
+                f,
+                "client account {} has {} held but needs {}",
+                client_id, held, required
+            ),
+            EngineError::UnknownTransaction { transaction_id } => {
+                write!(f, "transaction {} does not exist", transaction_id)
+            }
+            EngineError::UnknownClient { client_id } => {
+                write!(f, "client {} does not exist", client_id)
+            }
+            EngineError::ClientMismatch {
+                transaction_id,
+                client_id,
+                owner_id,
+            } => write!(
+                f,
+                "transaction {} belongs to client {}, not client {}",
+                transaction_id, owner_id, client_id
+            ),
+            EngineError::NotDisputed { transaction_id } => {
+                write!(f, "transaction {} has not been disputed", transaction_id)
+            }
+            EngineError::AlreadyDisputed { transaction_id } => {
+                write!(f, "transaction {} is already disputed", transaction_id)
+            }
+            EngineError::DisputeClosed {
+                transaction_id,
+                state,
+            } => write!(f, "transaction {} was already {}", transaction_id, state),
+            EngineError::Overflow { client_id } => {
+                write!(f, "transaction overflows client account {}", client_id)
+            }
+            EngineError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<StorageError> for EngineError {
+    fn from(err: StorageError) -> Self {
+        EngineError::Storage(err)
+    }
+}
@@ -0,0 +1,502 @@
+use crate::{ClientAccount, EngineError, Money, Transaction};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// What a fraud rule wants done about a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudAction {
+    // Only raise an alert, for someone to review
+    Flag,
+    // Lock the account like a chargeback would, and raise an alert
+    Lock,
+    // Refuse the transaction with `FraudSuspected`, and raise an alert
+    Reject,
+}
+
+impl fmt::Display for FraudAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FraudAction::Flag => write!(f, "flagged"),
+            FraudAction::Lock => write!(f, "locked"),
+            FraudAction::Reject => write!(f, "rejected"),
+        }
+    }
+}
+
+/// A rule's decision about one transaction, as handed to the alert
+/// handler and kept in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FraudDecision {
+    pub rule: String,
+    pub action: FraudAction,
+    pub client_id: u16,
+
+    // The transaction the rule fired on, for disputes the disputed one
+    pub transaction_id: u64,
+}
+
+impl fmt::Display for FraudDecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rule {} {} client {} on transaction {}",
+            self.rule, self.action, self.client_id, self.transaction_id
+        )
+    }
+}
+
+/// Looks for fraud in the transactions an engine handles.
+///
+/// Rules see every transaction, rejected ones included, and may keep
+/// whatever history they need. That history isn't persisted, only the
+/// effect of their decisions is: every decision is counted in the
+/// client's `ClientAccount::fraud_flags`.
+pub trait FraudRule: fmt::Debug + Send {
+    /// Short snake_case name, reported with the rule's decisions
+    fn name(&self) -> &str;
+
+    /// Called once the engine validated `transaction`, before it's
+    /// applied. `outcome` is the account as the transaction would leave
+    /// it, or why the engine rejects it. Another rule may still reject
+    /// it, so history is left to `commit`.
+    fn check(
+        &self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction>;
+
+    /// Called once the engine applied or rejected `transaction` for good.
+    /// `outcome` is the account it left, or why it was rejected, a rule's
+    /// `FraudSuspected` included. Not called when storage failed.
+    fn commit(
+        &mut self,
+        _transaction: &Transaction,
+        _outcome: Result<&ClientAccount, &EngineError>,
+    ) {
+    }
+}
+
+/// A deposit disputed after its funds were withdrawn.
+///
+/// The dispute itself is rejected for want of funds to hold, which
+/// leaves the client with money the bank is about to claw back.
+#[derive(Debug, Clone)]
+pub struct DepositWithdrawDispute {
+    pub action: FraudAction,
+}
+
+impl Default for DepositWithdrawDispute {
+    fn default() -> Self {
+        DepositWithdrawDispute {
+            action: FraudAction::Lock,
+        }
+    }
+}
+
+impl FraudRule for DepositWithdrawDispute {
+    fn name(&self) -> &str {
+        "deposit_withdraw_dispute"
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction> {
+        // Only disputed deposits are checked for available funds, a
+        // disputed withdrawal re-credits its amount instead
+        match (transaction, outcome) {
+            (Transaction::Dispute { .. }, Err(EngineError::InsufficientFunds { .. })) => {
+                Some(self.action)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// More than `max` disputes among a client's last `window` transactions.
+///
+/// Disputes count whether or not the engine accepted them, as repeated
+/// attempts are a signal too.
+#[derive(Debug, Clone)]
+pub struct DisputeVelocity {
+    pub max: usize,
+    pub action: FraudAction,
+    history: ClientWindow<bool>,
+}
+
+impl DisputeVelocity {
+    pub fn new(max: usize, window: usize, action: FraudAction) -> Self {
+        DisputeVelocity {
+            max,
+            action,
+            history: ClientWindow::new(window),
+        }
+    }
+}
+
+impl FraudRule for DisputeVelocity {
+    fn name(&self) -> &str {
+        "dispute_velocity"
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        _outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction> {
+        if !matches!(transaction, Transaction::Dispute { .. }) {
+            return None;
+        }
+
+        // The window ends with this dispute
+        let earlier = self
+            .history
+            .recent(transaction.client_id())
+            .take(self.history.size - 1)
+            .filter(|is_dispute| **is_dispute)
+            .count();
+        (earlier + 1 > self.max).then_some(self.action)
+    }
+
+    fn commit(
+        &mut self,
+        transaction: &Transaction,
+        _outcome: Result<&ClientAccount, &EngineError>,
+    ) {
+        let is_dispute = matches!(transaction, Transaction::Dispute { .. });
+        self.history.push(transaction.client_id(), is_dispute);
+    }
+}
+
+/// Withdrawals adding up to more than `limit` among a client's last
+/// `window` transactions. Only withdrawals the engine accepts count.
+#[derive(Debug, Clone)]
+pub struct WithdrawalVelocity {
+    pub limit: Money,
+    pub action: FraudAction,
+    history: ClientWindow<Money>,
+}
+
+impl WithdrawalVelocity {
+    pub fn new(limit: Money, window: usize, action: FraudAction) -> Self {
+        WithdrawalVelocity {
+            limit,
+            action,
+            history: ClientWindow::new(window),
+        }
+    }
+}
+
+impl FraudRule for WithdrawalVelocity {
+    fn name(&self) -> &str {
+        "withdrawal_velocity"
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction> {
+        let (Transaction::Withdraw { amount, .. }, Ok(_)) = (transaction, outcome) else {
+            return None;
+        };
+
+        // The window ends with this withdrawal. Saturates, as anything
+        // that large is over the limit anyway
+        let total = self
+            .history
+            .recent(transaction.client_id())
+            .take(self.history.size - 1)
+            .chain([amount])
+            .fold(Money::ZERO, |total, amount| {
+                total
+                    .checked_add(*amount)
+                    .unwrap_or(Money::from_raw(i64::MAX))
+            });
+        (total > self.limit).then_some(self.action)
+    }
+
+    fn commit(&mut self, transaction: &Transaction, outcome: Result<&ClientAccount, &EngineError>) {
+        // A rejected withdrawal never took the money out
+        let withdrawn = match (transaction, outcome) {
+            (Transaction::Withdraw { amount, .. }, Ok(_)) => *amount,
+            _ => Money::ZERO,
+        };
+        self.history.push(transaction.client_id(), withdrawn);
+    }
+}
+
+/// A withdrawal of at least the amount deposited by the client's
+/// transaction right before it
+#[derive(Debug, Clone)]
+pub struct DepositThenWithdrawal {
+    pub action: FraudAction,
+    last_deposit: HashMap<u16, Money>,
+}
+
+impl DepositThenWithdrawal {
+    pub fn new(action: FraudAction) -> Self {
+        DepositThenWithdrawal {
+            action,
+            last_deposit: HashMap::new(),
+        }
+    }
+}
+
+impl FraudRule for DepositThenWithdrawal {
+    fn name(&self) -> &str {
+        "deposit_then_withdrawal"
+    }
+
+    fn check(
+        &self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction> {
+        let (Transaction::Withdraw { amount, .. }, Ok(_)) = (transaction, outcome) else {
+            return None;
+        };
+        let deposited = self.last_deposit.get(&transaction.client_id())?;
+        (amount >= deposited).then_some(self.action)
+    }
+
+    fn commit(&mut self, transaction: &Transaction, outcome: Result<&ClientAccount, &EngineError>) {
+        // Any other transaction comes between the deposit and a withdrawal
+        let client_id = transaction.client_id();
+        match (transaction, outcome) {
+            (Transaction::Deposit { amount, .. }, Ok(_)) => {
+                self.last_deposit.insert(client_id, *amount);
+            }
+            _ => {
+                self.last_deposit.remove(&client_id);
+            }
+        }
+    }
+}
+
+// Each client's most recent values, up to `size` of them
+#[derive(Debug, Clone)]
+struct ClientWindow<T> {
+    size: usize,
+    clients: HashMap<u16, VecDeque<T>>,
+}
+
+impl<T> ClientWindow<T> {
+    fn new(size: usize) -> Self {
+        ClientWindow {
+            size: size.max(1),
+            clients: HashMap::new(),
+        }
+    }
+
+    // Newest first
+    fn recent(&self, client_id: u16) -> impl Iterator<Item = &T> {
+        self.clients.get(&client_id).into_iter().flatten().rev()
+    }
+
+    fn push(&mut self, client_id: u16, value: T) {
+        let values = self.clients.entry(client_id).or_default();
+        if values.len() == self.size {
+            values.pop_front();
+        }
+        values.push_back(value);
+    }
+}
+
+type AlertHandler = Box<dyn FnMut(&FraudDecision) + Send>;
+
+// The rules of an engine and where their alerts go
+#[derive(Default)]
+pub(crate) struct FraudRules {
+    rules: Vec<Box<dyn FraudRule>>,
+    on_alert: Option<AlertHandler>,
+}
+
+impl FraudRules {
+    pub(crate) fn push(&mut self, rule: Box<dyn FraudRule>) {
+        self.rules.push(rule);
+    }
+
+    pub(crate) fn on_alert(&mut self, handler: AlertHandler) {
+        self.on_alert = Some(handler);
+    }
+
+    pub(crate) fn check(
+        &self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Vec<FraudDecision> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let action = rule.check(transaction, outcome)?;
+                Some(FraudDecision {
+                    rule: rule.name().to_string(),
+                    action,
+                    client_id: transaction.client_id(),
+                    transaction_id: transaction.transaction_id(),
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn commit(
+        &mut self,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) {
+        for rule in &mut self.rules {
+            rule.commit(transaction, outcome);
+        }
+    }
+
+    pub(crate) fn alert(&mut self, decisions: &[FraudDecision]) {
+        if let Some(handler) = self.on_alert.as_mut() {
+            decisions.iter().for_each(handler);
+        }
+    }
+}
+
+impl fmt::Debug for FraudRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FraudRules")
+            .field("rules", &self.rules)
+            .field("on_alert", &self.on_alert.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(transaction_id: u64, amount: &str) -> anyhow::Result<Transaction> {
+        Ok(Transaction::Deposit {
+            transaction_id,
+            client_id: 1,
+            amount: amount.parse()?,
+        })
+    }
+
+    fn withdraw(transaction_id: u64, amount: &str) -> anyhow::Result<Transaction> {
+        Ok(Transaction::Withdraw {
+            transaction_id,
+            client_id: 1,
+            amount: amount.parse()?,
+        })
+    }
+
+    fn dispute(transaction_id: u64) -> Transaction {
+        Transaction::Dispute {
+            transaction_id,
+            client_id: 1,
+        }
+    }
+
+    // Checks `transaction` then commits it the way the engine would,
+    // rejected with `FraudSuspected` if the rule rejects it
+    fn handle(
+        rule: &mut impl FraudRule,
+        transaction: &Transaction,
+        outcome: Result<&ClientAccount, &EngineError>,
+    ) -> Option<FraudAction> {
+        let action = rule.check(transaction, outcome);
+        let suspected = EngineError::FraudSuspected {
+            client_id: transaction.client_id(),
+            rule: rule.name().to_string(),
+        };
+        let outcome = match action {
+            Some(FraudAction::Reject) if outcome.is_ok() => Err(&suspected),
+            _ => outcome,
+        };
+        rule.commit(transaction, outcome);
+        action
+    }
+
+    #[test]
+    fn counts_disputes_in_window() -> anyhow::Result<()> {
+        let account = ClientAccount::new(1);
+        let mut rule = DisputeVelocity::new(2, 4, FraudAction::Flag);
+        let mut check = |transaction: Transaction| handle(&mut rule, &transaction, Ok(&account));
+
+        assert_eq!(check(dispute(1)), None);
+        assert_eq!(check(deposit(2, "1.0")?), None);
+        assert_eq!(check(dispute(3)), None);
+        assert_eq!(check(dispute(4)), Some(FraudAction::Flag));
+
+        // The first dispute has left the window
+        assert_eq!(check(deposit(5, "1.0")?), None);
+        assert_eq!(check(dispute(6)), Some(FraudAction::Flag));
+        assert_eq!(check(deposit(7, "1.0")?), None);
+        assert_eq!(check(deposit(8, "1.0")?), None);
+        assert_eq!(check(dispute(9)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn sums_withdrawals_in_window() -> anyhow::Result<()> {
+        let account = ClientAccount::new(1);
+        let refused = EngineError::AccountLocked { client_id: 1 };
+        let mut rule = WithdrawalVelocity::new("10.0".parse()?, 3, FraudAction::Reject);
+        let mut check =
+            |transaction: Transaction, outcome| handle(&mut rule, &transaction, outcome);
+
+        assert_eq!(check(withdraw(1, "6.0")?, Ok(&account)), None);
+        assert_eq!(check(withdraw(2, "5.0")?, Err(&refused)), None);
+        assert_eq!(
+            check(withdraw(3, "5.0")?, Ok(&account)),
+            Some(FraudAction::Reject)
+        );
+
+        // Neither the refused nor the rejected one counts
+        assert_eq!(check(withdraw(4, "4.0")?, Ok(&account)), None);
+        assert_eq!(check(deposit(5, "9.0")?, Ok(&account)), None);
+        assert_eq!(check(deposit(6, "9.0")?, Ok(&account)), None);
+        assert_eq!(check(withdraw(7, "9.0")?, Ok(&account)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn counts_only_what_was_applied() -> anyhow::Result<()> {
+        let account = ClientAccount::new(1);
+        let mut rule = WithdrawalVelocity::new("10.0".parse()?, 3, FraudAction::Flag);
+        let withdrawal = withdraw(1, "8.0")?;
+
+        // Checking alone leaves no trace, another rule may reject it
+        assert_eq!(rule.check(&withdrawal, Ok(&account)), None);
+        let suspected = EngineError::FraudSuspected {
+            client_id: 1,
+            rule: "another_rule".to_string(),
+        };
+        rule.commit(&withdrawal, Err(&suspected));
+
+        assert_eq!(handle(&mut rule, &withdraw(2, "8.0")?, Ok(&account)), None);
+        assert_eq!(
+            handle(&mut rule, &withdraw(3, "8.0")?, Ok(&account)),
+            Some(FraudAction::Flag)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn spots_deposit_then_withdrawal() -> anyhow::Result<()> {
+        let account = ClientAccount::new(1);
+        let mut rule = DepositThenWithdrawal::new(FraudAction::Lock);
+        let mut check = |transaction: Transaction| handle(&mut rule, &transaction, Ok(&account));
+
+        assert_eq!(check(deposit(1, "5.0")?), None);
+        assert_eq!(check(withdraw(2, "4.0")?), None);
+        assert_eq!(check(deposit(3, "5.0")?), None);
+        assert_eq!(check(withdraw(4, "5.0")?), Some(FraudAction::Lock));
+
+        // Only right after the deposit
+        assert_eq!(check(deposit(5, "5.0")?), None);
+        assert_eq!(check(dispute(1)), None);
+        assert_eq!(check(withdraw(6, "5.0")?), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,709 @@
+use crate::{EngineError, Transaction};
+
+use csv::{ReaderBuilder, StringRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Fields of a transaction record, in the order headerless CSV has them
+pub const FIELDS: &[&str] = &["type", "client", "tx", "amount", "operator", "reason"];
+
+// Whitespace around fields is ignored and dispute
+// style records may leave out the amount column
+fn csv_reader_builder() -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder.flexible(true).trim(csv::Trim::All);
+    builder
+}
+
+/// One record of the input, as written and as parsed
+#[derive(Debug)]
+pub struct InputRecord<E> {
+    // Name of the input it came from, if the caller gave it one
+    pub source: Option<Arc<str>>,
+
+    // Line the record starts on, counting the CSV header if there is one
+    pub line: u64,
+
+    // Fields as they were written, a JSON line is kept as one field
+    pub fields: StringRecord,
+
+    pub transaction: Result<Transaction, E>,
+}
+
+impl<E> InputRecord<E> {
+    /// Where this record is, being the `record`th of the input
+    pub fn position(&self, record: u64) -> InputPosition {
+        InputPosition {
+            record,
+            source: self.source.as_deref().map(str::to_string),
+            line: self.line,
+        }
+    }
+
+    pub fn map_err<F>(self, op: impl FnOnce(E) -> F) -> InputRecord<F> {
+        InputRecord {
+            source: self.source,
+            line: self.line,
+            fields: self.fields,
+            transaction: self.transaction.map_err(op),
+        }
+    }
+}
+
+/// Where a record is in the input, the point a resumed run picks up
+/// after. The default is the start of the input.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputPosition {
+    // Counts records across every input of the run, so a resumed run
+    // knows how many to skip
+    pub record: u64,
+
+    // The record's source and line, to tell whether a resumed run was
+    // given the same input
+    pub source: Option<String>,
+    pub line: u64,
+}
+
+impl fmt::Display for InputPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "record {} ({} line {})", self.record, source, self.line),
+            None => write!(f, "record {} (line {})", self.record, self.line),
+        }
+    }
+}
+
+/// What a run does with records that are malformed or rejected by the engine
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    // Skip them and carry on with the next record
+    #[default]
+    Lenient,
+    // Stop at the first one
+    Strict,
+}
+
+// Reason code of records that couldn't be parsed into a transaction
+const MALFORMED: &str = "malformed";
+
+/// A record that was malformed or rejected by the engine, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    pub source: Option<Arc<str>>,
+    pub line: u64,
+
+    // `malformed` or the `EngineError::code` of the rejection
+    pub code: &'static str,
+    pub message: String,
+    pub fields: StringRecord,
+}
+
+impl Reject {
+    pub fn malformed(
+        source: Option<Arc<str>>,
+        line: u64,
+        fields: StringRecord,
+        err: &impl fmt::Display,
+    ) -> Self {
+        Reject {
+            source,
+            line,
+            code: MALFORMED,
+            message: err.to_string(),
+            fields,
+        }
+    }
+
+    pub fn rejected(
+        source: Option<Arc<str>>,
+        line: u64,
+        fields: StringRecord,
+        err: &EngineError,
+    ) -> Self {
+        Reject {
+            source,
+            line,
+            code: err.code(),
+            message: err.to_string(),
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(source) = &self.source {
+            write!(f, "{} ", source)?;
+        }
+        write!(f, "line {} ({}): {}", self.line, self.code, self.message)
+    }
+}
+
+impl std::error::Error for Reject {}
+
+/// A name that isn't one of `Transaction::TYPES`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownType(pub String);
+
+impl fmt::Display for UnknownType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown transaction type `{}`, expected one of {}",
+            self.0,
+            Transaction::TYPES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownType {}
+
+/// Extra names for the transaction types, e.g. `credit` for `deposit`.
+/// Like the built-in names, they're matched regardless of case.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Aliases(HashMap<String, &'static str>);
+
+impl Aliases {
+    /// Reads `alias` as `type_name`, which has to be one of `Transaction::TYPES`
+    pub fn insert(&mut self, alias: &str, type_name: &str) -> Result<(), UnknownType> {
+        let type_name =
+            Transaction::type_name(type_name).ok_or_else(|| UnknownType(type_name.to_string()))?;
+        self.0.insert(alias.to_ascii_lowercase(), type_name);
+        Ok(())
+    }
+
+    // The built-in name `name` stands for, if it's an alias
+    fn resolve(&self, name: &str) -> Option<&'static str> {
+        if self.0.is_empty() {
+            return None;
+        }
+        self.0.get(&name.to_ascii_lowercase()).copied()
+    }
+}
+
+/// Where a CSV field is read from: a header name, or a column
+/// number counting from 1, e.g. `customer_id` or `2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Position(usize),
+}
+
+impl FromStr for Column {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.parse::<usize>() {
+            Ok(position) if position > 0 => Column::Position(position),
+            _ => Column::Name(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Column::Name(name) => write!(f, "`{}`", name),
+            Column::Position(position) => write!(f, "{}", position),
+        }
+    }
+}
+
+/// A name that isn't one of `FIELDS`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownField(pub String);
+
+impl fmt::Display for UnknownField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown field `{}`, expected one of {}",
+            self.0,
+            FIELDS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownField {}
+
+/// How input files are read, on top of what every format accepts
+#[derive(Debug, Default, Clone)]
+pub struct ReadOptions {
+    pub aliases: Aliases,
+
+    // CSV only: the file has no header, fields are read by position
+    // in `FIELDS` order unless mapped to other columns
+    pub headerless: bool,
+
+    // CSV only: fields read from another column than the one named after them
+    columns: Vec<(&'static str, Column)>,
+}
+
+impl ReadOptions {
+    /// Reads `field`, one of `FIELDS`, from `column` instead
+    pub fn map_column(&mut self, field: &str, column: Column) -> Result<(), UnknownField> {
+        let field = FIELDS
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(field.trim()))
+            .ok_or_else(|| UnknownField(field.to_string()))?;
+        self.columns.retain(|(mapped, _)| mapped != field);
+        self.columns.push((field, column));
+        Ok(())
+    }
+
+    // The header records are deserialized against: the file's own, or
+    // `FIELDS` without one, with each mapped column renamed to its field.
+    // Columns are all looked up in the file's own header, so mappings
+    // can swap columns whatever order they're given in.
+    fn csv_headers(&self, file_headers: Option<&StringRecord>) -> io::Result<StringRecord> {
+        let mut headers = match file_headers {
+            Some(headers) => headers.iter().map(str::to_string).collect::<Vec<_>>(),
+            None => FIELDS.iter().map(|field| field.to_string()).collect(),
+        };
+
+        let mut indexes = Vec::with_capacity(self.columns.len());
+        for (field, column) in &self.columns {
+            let index = match (column, file_headers) {
+                (Column::Position(position), _) => position - 1,
+                (Column::Name(name), Some(file_headers)) => file_headers
+                    .iter()
+                    .position(|header| header.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("no column {} to read `{}` from", column, field),
+                        )
+                    })?,
+                (Column::Name(_), None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("column {} needs a header, use its number instead", column),
+                    ));
+                }
+            };
+            indexes.push((*field, index));
+        }
+
+        // A column that happens to be named after a mapped field is ignored now
+        for header in headers.iter_mut() {
+            if indexes.iter().any(|(field, _)| header == field) {
+                header.clear();
+            }
+        }
+        for (field, index) in indexes {
+            if index >= headers.len() {
+                headers.resize(index + 1, String::new());
+            }
+            headers[index] = field.to_string();
+        }
+
+        Ok(StringRecord::from(headers))
+    }
+}
+
+/// Parses every record of a CSV file with a `type, client, tx, amount` header.
+/// Columns can be in any order and any others are ignored.
+pub fn read_csv<R: Read>(reader: R) -> impl Iterator<Item = InputRecord<csv::Error>> {
+    read_csv_with(reader, ReadOptions::default())
+}
+
+/// Same as `read_csv`, with type aliases and the like from `options`
+pub fn read_csv_with<R: Read>(
+    reader: R,
+    options: ReadOptions,
+) -> impl Iterator<Item = InputRecord<csv::Error>> {
+    let mut reader = csv_reader_builder()
+        .has_headers(!options.headerless)
+        .from_reader(reader);
+    let file_headers = match options.headerless {
+        true => Ok(None),
+        false => reader.headers().map(|headers| Some(headers.clone())),
+    };
+    let (headers, mut header_error) = match file_headers
+        .and_then(|file_headers| Ok(options.csv_headers(file_headers.as_ref())?))
+    {
+        Ok(headers) => (headers, None),
+        Err(err) => (StringRecord::new(), Some(err)),
+    };
+    let type_column = headers.iter().position(|header| header == "type");
+
+    let mut done = false;
+    std::iter::from_fn(move || {
+        // Nothing can be parsed without the header
+        if let Some(err) = header_error.take() {
+            done = true;
+            return Some(InputRecord {
+                source: None,
+                line: 1,
+                fields: StringRecord::new(),
+                transaction: Err(err),
+            });
+        }
+        if done {
+            return None;
+        }
+
+        let mut fields = StringRecord::new();
+        match reader.read_record(&mut fields) {
+            Ok(false) => None,
+            Ok(true) => {
+                // Parsed with any alias swapped for its type, the original
+                // fields are kept as they were written
+                let alias = type_column.and_then(|column| {
+                    Some((column, options.aliases.resolve(fields.get(column)?)?))
+                });
+                let transaction = match alias {
+                    Some((column, type_name)) => {
+                        let renamed = fields
+                            .iter()
+                            .enumerate()
+                            .map(|(i, field)| if i == column { type_name } else { field })
+                            .collect();
+                        parse_csv_record(&renamed, &headers)
+                    }
+                    None => parse_csv_record(&fields, &headers),
+                };
+
+                Some(InputRecord {
+                    source: None,
+                    line: fields.position().map_or(0, |position| position.line()),
+                    transaction,
+                    fields,
+                })
+            }
+            Err(err) => {
+                // Nothing more can be read after an I/O error
+                done = matches!(err.kind(), csv::ErrorKind::Io(_));
+                Some(InputRecord {
+                    source: None,
+                    line: err.position().map_or(0, |position| position.line()),
+                    fields,
+                    transaction: Err(err),
+                })
+            }
+        }
+    })
+}
+
+/// Parses one JSON object per line, with the same fields as a CSV record,
+/// e.g. `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+///
+/// Amounts may be numbers or strings and go through the same `Money`
+/// validation as CSV. Blank lines are skipped.
+pub fn read_jsonl<R: Read>(reader: R) -> impl Iterator<Item = InputRecord<serde_json::Error>> {
+    read_jsonl_with(reader, ReadOptions::default())
+}
+
+/// Same as `read_jsonl`, with type aliases and the like from `options`
+pub fn read_jsonl_with<R: Read>(
+    reader: R,
+    options: ReadOptions,
+) -> impl Iterator<Item = InputRecord<serde_json::Error>> {
+    BufReader::new(reader)
+        .lines()
+        .zip(1..)
+        .filter(|(text, _)| !text.as_ref().is_ok_and(|text| text.trim().is_empty()))
+        .map(move |(text, line)| match text {
+            Ok(text) => InputRecord {
+                source: None,
+                line,
+                transaction: parse_json_record(&text, &options.aliases),
+                fields: StringRecord::from(vec![text]),
+            },
+            Err(err) => InputRecord {
+                source: None,
+                line,
+                fields: StringRecord::new(),
+                transaction: Err(serde_json::Error::io(err)),
+            },
+        })
+}
+
+fn parse_json_record(text: &str, aliases: &Aliases) -> Result<Transaction, serde_json::Error> {
+    let mut value: serde_json::Value = serde_json::from_str(text)?;
+    if let Some(kind) = value.get_mut("type")
+        && let Some(type_name) = kind.as_str().and_then(|name| aliases.resolve(name))
+    {
+        *kind = type_name.into();
+    }
+    transaction_from_json(value)
+}
+
+/// Reads a transaction from a JSON object with the same fields as a CSV
+/// record.
+///
+/// A numeric amount is taken from its digits as written rather than
+/// through an `f64`, so it's validated exactly like a CSV one.
+pub fn transaction_from_json(
+    mut value: serde_json::Value,
+) -> Result<Transaction, serde_json::Error> {
+    if let Some(amount) = value.get_mut("amount")
+        && let serde_json::Value::Number(number) = amount
+    {
+        *amount = number.to_string().into();
+    }
+    serde_json::from_value(value)
+}
+
+/// Splits a single line of CSV into its fields
+pub fn parse_csv_line(line: &str) -> Result<StringRecord, csv::Error> {
+    let mut record = StringRecord::new();
+    csv_reader_builder()
+        .has_headers(false)
+        .from_reader(line.as_bytes())
+        .read_record(&mut record)?;
+    Ok(record)
+}
+
+/// Parses one record against the header read at the start of its file
+pub fn parse_csv_record(
+    record: &StringRecord,
+    headers: &StringRecord,
+) -> Result<Transaction, csv::Error> {
+    record.deserialize(Some(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_jsonl_like_csv() -> anyhow::Result<()> {
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 1.5
+withdrawal, 1, 2, 0.25
+dispute, 1, 1,
+resolve, 1, 1,
+chargeback, 1, 1,
+deposit, 1, 3, 0.12345
+deposit, 1, 4, -1";
+        let jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "0.25"}
+
+{"type": "dispute", "client": 1, "tx": 1}
+{"type": "resolve", "client": 1, "tx": 1, "amount": null}
+{"type": "chargeback", "client": 1, "tx": 1, "amount": ""}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 0.12345}
+{"type": "deposit", "client": 1, "tx": 4, "amount": -1}"#;
+
+        let from_csv = read_csv(csv.as_bytes())
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        let from_jsonl = read_jsonl(jsonl.as_bytes())
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(from_jsonl, from_csv);
+
+        // Too many decimal places, rejected by both
+        assert_eq!(from_jsonl[5], None);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_amounts_as_written() -> anyhow::Result<()> {
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 123456789012.3456
+deposit, 1, 2, 2.00000000000000001";
+        let jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 123456789012.3456}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 2.00000000000000001}"#;
+
+        let from_csv = read_csv(csv.as_bytes())
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        let from_jsonl = read_jsonl(jsonl.as_bytes())
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(from_jsonl, from_csv);
+
+        // More digits than a float holds, but exact
+        assert_eq!(
+            from_csv[0],
+            Some(Transaction::Deposit {
+                transaction_id: 1,
+                client_id: 1,
+                amount: "123456789012.3456".parse()?,
+            })
+        );
+        // Too many decimal places, however close to 2 it is
+        assert_eq!(from_csv[1], None);
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_line_numbers_and_fields() {
+        let csv = "type, client, tx, amount\ndeposit, 1, 1, 1\n\"with\nbreak\", 1, 2, 1\noops, 1\n";
+        let records = read_csv(csv.as_bytes())
+            .map(|record| (record.line, record.fields, record.transaction.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (2, StringRecord::from(vec!["deposit", "1", "1", "1"]), true),
+                (
+                    3,
+                    StringRecord::from(vec!["with\nbreak", "1", "2", "1"]),
+                    false
+                ),
+                (5, StringRecord::from(vec!["oops", "1"]), false),
+            ]
+        );
+
+        let jsonl = "\n{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1}\n{oops}\n";
+        let records = read_jsonl(jsonl.as_bytes())
+            .map(|record| (record.line, record.fields, record.transaction.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                (
+                    2,
+                    StringRecord::from(vec![
+                        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1}"#
+                    ]),
+                    true
+                ),
+                (3, StringRecord::from(vec!["{oops}"]), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_type_aliases() -> anyhow::Result<()> {
+        let mut options = ReadOptions::default();
+        options.aliases.insert("Credit", "deposit")?;
+        options.aliases.insert("debit", "WITHDRAWAL")?;
+        options.aliases.insert("reversal", "chargeback")?;
+        assert_eq!(
+            options.aliases.insert("refund", "payback"),
+            Err(UnknownType("payback".to_string()))
+        );
+
+        let csv = "type, client, tx, amount
+CREDIT, 1, 1, 2
+debit, 1, 2, 1
+Reversal, 1, 1,
+Deposit, 1, 3, 1
+refund, 1, 4, 1";
+        let jsonl = r#"{"type": "CREDIT", "client": 1, "tx": 1, "amount": 2}
+{"type": "debit", "client": 1, "tx": 2, "amount": "1"}
+{"type": "Reversal", "client": 1, "tx": 1}
+{"type": "Deposit", "client": 1, "tx": 3, "amount": 1}
+{"type": "refund", "client": 1, "tx": 4, "amount": 1}"#;
+
+        let amount = |amount: &str| amount.parse::<crate::Money>();
+        let expected = vec![
+            Some(Transaction::Deposit {
+                transaction_id: 1,
+                client_id: 1,
+                amount: amount("2")?,
+            }),
+            Some(Transaction::Withdraw {
+                transaction_id: 2,
+                client_id: 1,
+                amount: amount("1")?,
+            }),
+            Some(Transaction::Chargeback {
+                transaction_id: 1,
+                client_id: 1,
+            }),
+            Some(Transaction::Deposit {
+                transaction_id: 3,
+                client_id: 1,
+                amount: amount("1")?,
+            }),
+            None,
+        ];
+
+        let records = read_csv_with(csv.as_bytes(), options.clone()).collect::<Vec<_>>();
+        assert_eq!(records[0].fields[0], *"CREDIT");
+        let from_csv = records
+            .into_iter()
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(from_csv, expected);
+
+        let from_jsonl = read_jsonl_with(jsonl.as_bytes(), options)
+            .map(|record| record.transaction.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(from_jsonl, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn maps_columns() -> anyhow::Result<()> {
+        let parsed = |csv: &str, options: ReadOptions| {
+            read_csv_with(csv.as_bytes(), options)
+                .map(|record| record.transaction.map_err(|err| err.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let deposit = Transaction::Deposit {
+            transaction_id: 7,
+            client_id: 3,
+            amount: "1.5".parse()?,
+        };
+        let dispute = Transaction::Dispute {
+            transaction_id: 7,
+            client_id: 3,
+        };
+
+        // Reordered, with a column nobody asked for
+        let csv = "amount, note, tx, type, client\n1.5, hi, 7, deposit, 3\n, , 7, dispute, 3";
+        assert_eq!(
+            parsed(csv, ReadOptions::default()),
+            vec![Ok(deposit.clone()), Ok(dispute.clone())]
+        );
+
+        // Renamed columns, one of them shadowing a column called `client`
+        let mut options = ReadOptions::default();
+        options.map_column("client", "Customer_ID".parse()?)?;
+        options.map_column("tx", "txn".parse()?)?;
+        let csv = "kind, type, client, customer_id, txn, amount\nx, deposit, 9, 3, 7, 1.5";
+        assert_eq!(parsed(csv, options), vec![Ok(deposit.clone())]);
+
+        // Headerless, in the default order or by column number
+        let options = ReadOptions {
+            headerless: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            parsed("deposit, 3, 7, 1.5\ndispute, 3, 7,", options.clone()),
+            vec![Ok(deposit.clone()), Ok(dispute)]
+        );
+
+        let mut positional = options.clone();
+        positional.map_column("amount", "1".parse()?)?;
+        positional.map_column("type", "3".parse()?)?;
+        positional.map_column("client", "4".parse()?)?;
+        positional.map_column("tx", "5".parse()?)?;
+        assert_eq!(
+            parsed("1.5, 2024-01-01, deposit, 3, 7", positional),
+            vec![Ok(deposit)]
+        );
+
+        // Names can't be looked up without a header, nor missing ones with one
+        let mut named = options;
+        named.map_column("tx", "txn".parse()?)?;
+        let records = parsed("deposit, 3, 7, 1.5", named);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_err());
+
+        let mut missing = ReadOptions::default();
+        missing.map_column("tx", "txn".parse()?)?;
+        let records = parsed("type, client, tx, amount\ndeposit, 3, 7, 1.5", missing);
+        assert_eq!(
+            records,
+            vec![Err("no column `txn` to read `tx` from".to_string())]
+        );
+
+        assert_eq!(
+            ReadOptions::default().map_column("customer", Column::Position(1)),
+            Err(UnknownField("customer".to_string()))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{FraudDecision, InputPosition, StorageError, Transaction};
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// One accepted transaction, numbered in the order it was applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub transaction: Transaction,
+
+    // Fraud rule decisions taken on the transaction, replayed along with it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decisions: Vec<FraudDecision>,
+
+    // A rejected transaction is only journaled for the account it opened
+    // and its decisions
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rejected: bool,
+
+    // Where the transaction was read from, when it came from an input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<InputPosition>,
+}
+
+/// Write-ahead journal of accepted transactions, and of rejected ones
+/// that opened an account or that fraud rules took a decision on.
+///
+/// An append-only file with one JSON `JournalEntry` per line. Every
+/// append is synced to disk before it returns, so anything the engine
+/// applied can be rebuilt by replaying the file from the start.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    last_sequence: u64,
+    last_input: Option<InputPosition>,
+}
+
+fn journal_err(path: &Path, err: impl std::fmt::Display) -> StorageError {
+    StorageError::new(format!("journal {}: {}", path.display(), err))
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, creating it if needed.
+    ///
+    /// A crash can leave half a line at the end of the file, which is
+    /// cut off here since its transaction was never applied.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|err| journal_err(&path, err))?;
+
+        let mut last_sequence = 0;
+        let mut last_input = None;
+        let mut valid_len = 0;
+        {
+            let mut reader = BufReader::new(&file);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                let read = reader
+                    .read_line(&mut line)
+                    .map_err(|err| journal_err(&path, err))?;
+                if read == 0 || !line.ends_with('\n') {
+                    break;
+                }
+
+                let entry: JournalEntry =
+                    serde_json::from_str(&line).map_err(|err| journal_err(&path, err))?;
+                last_sequence = entry.sequence;
+                last_input = entry.input.or(last_input);
+                valid_len += read as u64;
+            }
+        }
+
+        let mut file = file;
+        file.set_len(valid_len)
+            .and_then(|_| file.seek(SeekFrom::End(0)))
+            .map_err(|err| journal_err(&path, err))?;
+
+        Ok(Journal {
+            path,
+            file,
+            last_sequence,
+            last_input,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Sequence number of the newest entry, 0 when empty
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    // Input position of the newest entry that has one
+    pub fn last_input(&self) -> Option<&InputPosition> {
+        self.last_input.as_ref()
+    }
+
+    /// Durably appends `transaction`, returning its sequence number
+    pub fn append(&mut self, transaction: Transaction) -> Result<u64, StorageError> {
+        self.append_decisions(transaction, false, Vec::new(), None)
+    }
+
+    /// Like `append`, along with the fraud rule decisions taken on the
+    /// transaction, which may have been `rejected`, and where in the
+    /// input it was read from
+    pub fn append_decisions(
+        &mut self,
+        transaction: Transaction,
+        rejected: bool,
+        decisions: Vec<FraudDecision>,
+        input: Option<InputPosition>,
+    ) -> Result<u64, StorageError> {
+        let entry = JournalEntry {
+            sequence: self.last_sequence + 1,
+            transaction,
+            decisions,
+            rejected,
+            input,
+        };
+
+        let mut line = serde_json::to_vec(&entry).map_err(|err| journal_err(&self.path, err))?;
+        line.push(b'\n');
+
+        self.file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|err| journal_err(&self.path, err))?;
+
+        self.last_sequence = entry.sequence;
+        if entry.input.is_some() {
+            self.last_input = entry.input;
+        }
+        Ok(entry.sequence)
+    }
+
+    /// Reads back every complete entry of the journal at `path`, oldest first
+    pub fn entries(
+        path: impl AsRef<Path>,
+    ) -> Result<impl Iterator<Item = Result<JournalEntry, StorageError>>, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path).map_err(|err| journal_err(&path, err))?;
+
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        Ok(std::iter::from_fn(move || {
+            line.clear();
+            match reader.read_line(&mut line) {
+                // A torn last line was never applied, treat it as the end
+                Ok(0) => None,
+                Ok(_) if !line.ends_with('\n') => None,
+                Ok(_) => Some(serde_json::from_str(&line).map_err(|err| journal_err(&path, err))),
+                Err(err) => Some(Err(journal_err(&path, err))),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn appends_and_drops_torn_tail() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 1,
+            client_id: 1,
+            amount: "1.5".parse()?,
+        };
+        let dispute = Transaction::Dispute {
+            transaction_id: 1,
+            client_id: 1,
+        };
+
+        {
+            let mut journal = Journal::open(&path)?;
+            assert_eq!(journal.append(deposit.clone())?, 1);
+            assert_eq!(journal.append(dispute.clone())?, 2);
+        }
+
+        // Simulate a crash halfway through writing a third entry
+        let mut contents = fs::read(&path)?;
+        contents.extend_from_slice(br#"{"sequence":3,"transa"#);
+        fs::write(&path, contents)?;
+
+        let entries = Journal::entries(&path)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            entries,
+            vec![
+                JournalEntry {
+                    sequence: 1,
+                    transaction: deposit.clone(),
+                    decisions: Vec::new(),
+                    rejected: false,
+                    input: None,
+                },
+                JournalEntry {
+                    sequence: 2,
+                    transaction: dispute,
+                    decisions: Vec::new(),
+                    rejected: false,
+                    input: None,
+                },
+            ]
+        );
+
+        let mut journal = Journal::open(&path)?;
+        assert_eq!(journal.last_sequence(), 2);
+        assert_eq!(journal.append(deposit)?, 3);
+        assert_eq!(Journal::entries(&path)?.count(), 3);
+        Ok(())
+    }
+}
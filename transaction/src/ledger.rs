@@ -0,0 +1,57 @@
+use crate::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Where a ledger entry is in its dispute lifecycle.
+///
+/// Entries start `Settled`, can be disputed once, and a dispute
+/// ends either `Resolved` or `ChargedBack`. Both are final.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    #[default]
+    Settled,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// Whether moving from this state to `next` is a legal step
+    pub fn can_transition_to(self, next: DisputeState) -> bool {
+        matches!(
+            (self, next),
+            (DisputeState::Settled, DisputeState::Disputed)
+                | (DisputeState::Disputed, DisputeState::Resolved)
+                | (DisputeState::Disputed, DisputeState::ChargedBack)
+        )
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisputeState::Settled => write!(f, "settled"),
+            DisputeState::Disputed => write!(f, "disputed"),
+            DisputeState::Resolved => write!(f, "resolved"),
+            DisputeState::ChargedBack => write!(f, "charged back"),
+        }
+    }
+}
+
+/// An accepted deposit, withdrawal or administrative operation along
+/// with its dispute state. Only deposits and withdrawals leave `Settled`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LedgerEntry {
+    pub transaction: Transaction,
+    pub state: DisputeState,
+}
+
+impl LedgerEntry {
+    pub fn new(transaction: Transaction) -> Self {
+        LedgerEntry {
+            transaction,
+            state: DisputeState::Settled,
+        }
+    }
+}
@@ -1,10 +1,35 @@
 pub mod client;
+pub mod disk;
+pub mod error;
+pub mod fraud;
+pub mod input;
+pub mod journal;
+pub mod ledger;
+pub mod money;
+pub mod policy;
+pub mod snapshot;
+pub mod storage;
+pub mod stream;
 pub mod transaction;
 
 pub use client::ClientAccount;
+pub use disk::DiskStorage;
+pub use error::EngineError;
+pub use fraud::{FraudAction, FraudDecision, FraudRule};
+pub use input::{InputPosition, InputRecord, Reject, Validation};
+pub use journal::{Journal, JournalEntry};
+pub use ledger::{DisputeState, LedgerEntry};
+pub use money::Money;
+pub use policy::LockPolicy;
+pub use snapshot::Snapshot;
+pub use storage::{AccountRepository, LedgerRepository, StorageError};
+pub use stream::{Outcome, SharedEngine};
 pub use transaction::Transaction;
 
+use fraud::FraudRules;
 use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 // Notes on `Ledger` and `AccountStore`:
 // Ideally, some chronologically sorted timestamped structure,
@@ -12,195 +37,743 @@ use std::collections::HashMap;
 // a map here and no replay for the accounts and ledger.
 
 // Stores all withdrawalls and deposits
-pub type Ledger = HashMap<u64, Transaction>;
+// with where they are in their dispute lifecycle
+pub type Ledger = HashMap<u64, LedgerEntry>;
 
 // Stores a clients details from the exercise
 pub type AccountStore = HashMap<u16, ClientAccount>;
 
-#[derive(Debug, Default)]
-pub struct TransactionEngine {
-    pub client_accounts: AccountStore,
-    pub ledger: Ledger,
+// Generic over where accounts and the ledger live,
+// defaulting to the in-memory maps above
+#[derive(Debug)]
+pub struct TransactionEngine<A = AccountStore, L = Ledger>
+where
+    A: AccountRepository,
+    L: LedgerRepository,
+{
+    pub client_accounts: A,
+    pub ledger: L,
+    journal: Option<Journal>,
+    snapshot_path: Option<PathBuf>,
+    lock_policy: LockPolicy,
+    fraud: FraudRules,
 }
 
-impl TransactionEngine {
-    fn get_or_create_client(&mut self, client_id: u16) -> &mut ClientAccount {
-        self.client_accounts
-            .entry(client_id)
-            .or_insert(ClientAccount::new(client_id))
+// What accepting a transaction changes, worked out before anything is written
+struct Update {
+    account: ClientAccount,
+    transaction_id: u64,
+    entry: LedgerEntry,
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        TransactionEngine::new(AccountStore::default(), Ledger::default())
     }
+}
 
-    pub fn handle(&mut self, transaction: Transaction) -> anyhow::Result<()> {
-        // We only need to track the Deposits and Withdrawals in these usecases
-        match transaction {
-            Transaction::Deposit { transaction_id, .. }
-            | Transaction::Withdraw { transaction_id, .. } => {
-                if self.ledger.contains_key(&transaction_id) {
-                    anyhow::bail!(format!("transaction {} is not unique", transaction_id));
-                }
+impl<A: AccountRepository, L: LedgerRepository> TransactionEngine<A, L> {
+    pub fn new(client_accounts: A, ledger: L) -> Self {
+        TransactionEngine {
+            client_accounts,
+            ledger,
+            journal: None,
+            snapshot_path: None,
+            lock_policy: LockPolicy::default(),
+            fraud: FraudRules::default(),
+        }
+    }
+
+    /// Refuses the transactions `lock_policy` blocks on locked accounts,
+    /// instead of only withdrawals
+    pub fn with_lock_policy(self, lock_policy: LockPolicy) -> Self {
+        TransactionEngine {
+            lock_policy,
+            ..self
+        }
+    }
+
+    pub fn lock_policy(&self) -> LockPolicy {
+        self.lock_policy
+    }
+
+    /// Runs `rule` on every transaction from here on, after the rules
+    /// added before it
+    pub fn with_fraud_rule(mut self, rule: impl FraudRule + 'static) -> Self {
+        self.fraud.push(Box::new(rule));
+        self
+    }
+
+    /// Calls `handler` with every fraud rule decision, once it took effect
+    pub fn on_fraud_alert(mut self, handler: impl FnMut(&FraudDecision) + Send + 'static) -> Self {
+        self.fraud.on_alert(Box::new(handler));
+        self
+    }
+
+    /// Rebuilds the engine by replaying the journal at `path`, then
+    /// journals every transaction accepted from here on to the same file.
+    ///
+    /// Returns how many transactions were replayed. Replay starts from
+    /// the current state, so it should be an empty engine.
+    pub fn recover(&mut self, path: impl AsRef<Path>) -> Result<u64, EngineError> {
+        self.replay(path, 0)
+    }
+
+    /// Like `recover`, but starts from the snapshot at `snapshot_path`
+    /// if there is one and only replays the journal entries after it.
+    ///
+    /// From then on `save_snapshot` replaces it.
+    pub fn restore(
+        &mut self,
+        snapshot_path: impl AsRef<Path>,
+        journal_path: impl AsRef<Path>,
+    ) -> Result<u64, EngineError> {
+        let mut sequence = 0;
+        if let Some(snapshot) = Snapshot::load(&snapshot_path)? {
+            for account in snapshot.accounts {
+                self.client_accounts.save(account)?;
+            }
+            for (transaction_id, entry) in snapshot.ledger {
+                self.ledger.save(transaction_id, entry)?;
+            }
+            sequence = snapshot.sequence;
+        }
+
+        let replayed = self.replay(journal_path, sequence)?;
+        self.snapshot_path = Some(snapshot_path.as_ref().to_path_buf());
+        Ok(replayed)
+    }
+
+    fn replay(&mut self, path: impl AsRef<Path>, after: u64) -> Result<u64, EngineError> {
+        let journal = Journal::open(&path)?;
+
+        // New entries would be numbered as if already in the snapshot
+        if journal.last_sequence() < after {
+            return Err(StorageError::new(format!(
+                "journal {} ends at entry {} but the snapshot includes entry {}",
+                path.as_ref().display(),
+                journal.last_sequence(),
+                after
+            ))
+            .into());
+        }
+
+        let mut replayed = 0;
+        for entry in Journal::entries(&path)? {
+            let entry = entry?;
+            if entry.sequence <= after {
+                continue;
+            }
 
-                self.ledger.insert(transaction_id, transaction);
+            // Every entry was accepted once, so a rejection means the
+            // journal doesn't belong to this state. Whatever lock policy
+            // accepted it, it's applied even if the current one disagrees.
+            if entry.rejected {
+                // Rejected entries are only journaled with an account to
+                // apply to, which they may have been the one to open
+                self.get_or_create_client(entry.transaction.client_id())?;
+            } else {
+                let update = self.prepare(&entry.transaction, LockPolicy::NONE)?;
+                self.apply(update)?;
             }
+            self.apply_fraud(&entry.decisions)?;
+            replayed += 1;
+        }
+
+        self.journal = Some(journal);
+        Ok(replayed)
+    }
+
+    /// Copies the current accounts and ledger, up to the last journal entry
+    pub fn snapshot(&self) -> Result<Snapshot, StorageError> {
+        Ok(Snapshot {
+            sequence: self.journal.as_ref().map_or(0, Journal::last_sequence),
+            accounts: self.client_accounts.all()?,
+            ledger: self.ledger.all()?,
+        })
+    }
 
-            // We don't need to store the dispute, chargeback, resolves
-            // plus they dont have a unique ID for the key and generating
-            // one could cause clashes for upcoming transactions. This is
-            // a problem I'd solve given more time
-            _ => {}
+    /// Replaces the snapshot `restore` started from with the current
+    /// state. It copies every account and ledger entry, so callers pick
+    /// when it's worth it, like at the end of a run.
+    pub fn save_snapshot(&self) -> Result<(), StorageError> {
+        match &self.snapshot_path {
+            Some(path) => self.snapshot()?.save(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Last input record applied as of the last commit of both
+    /// repositories, or the last journaled one if that's further
+    pub fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        let accounts = self.client_accounts.checkpoint()?;
+        let ledger = self.ledger.checkpoint()?;
+        let mut checkpoint = if accounts.record <= ledger.record {
+            accounts
+        } else {
+            ledger
+        };
+
+        if let Some(journaled) = self.journal.as_ref().and_then(Journal::last_input)
+            && journaled.record > checkpoint.record
+        {
+            checkpoint = journaled.clone();
+        }
+        Ok(checkpoint)
+    }
+
+    /// Commits both repositories, see `AccountRepository::commit`
+    pub fn commit(&mut self, checkpoint: &InputPosition) -> Result<(), StorageError> {
+        self.client_accounts.commit(checkpoint)?;
+        self.ledger.commit(checkpoint)
+    }
+
+    fn find_or_new_client(&self, client_id: u16) -> Result<ClientAccount, EngineError> {
+        Ok(self
+            .client_accounts
+            .find(client_id)?
+            .unwrap_or_else(|| ClientAccount::new(client_id)))
+    }
+
+    fn get_or_create_client(&mut self, client_id: u16) -> Result<ClientAccount, EngineError> {
+        if let Some(account) = self.client_accounts.find(client_id)? {
+            return Ok(account);
+        }
+
+        let account = ClientAccount::new(client_id);
+        self.client_accounts.save(account.clone())?;
+        Ok(account)
+    }
+
+    pub fn handle(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        self.handle_from(transaction, None)
+    }
+
+    // Handles `transaction`, journaling where in the input it came from
+    fn handle_from(
+        &mut self,
+        transaction: Transaction,
+        input: Option<InputPosition>,
+    ) -> Result<(), EngineError> {
+        let prepared = self.prepare(&transaction, self.lock_policy);
+        let decisions = self.fraud.check(
+            &transaction,
+            prepared.as_ref().map(|update| &update.account),
+        );
+
+        // The first rule to reject a transaction the engine would accept
+        // is the reason given
+        let rejected_by = decisions
+            .iter()
+            .find(|decision| decision.action == FraudAction::Reject);
+        let prepared = match (prepared, rejected_by) {
+            (Ok(_), Some(decision)) => Err(EngineError::FraudSuspected {
+                client_id: decision.client_id,
+                rule: decision.rule.clone(),
+            }),
+            (prepared, _) => prepared,
+        };
+
+        let update = match prepared {
+            Ok(update) => update,
+            Err(err) => {
+                // A rejected deposit or withdrawal still opens the client's
+                // account, and so does a dispute for a client we've never seen.
+                // Support can't open one by freezing or closing it though.
+                let creates_client = match err {
+                    EngineError::DuplicateTransaction { .. } | EngineError::Storage(_) => false,
+                    EngineError::UnknownClient { .. } => !transaction.is_admin(),
+                    _ => matches!(
+                        transaction,
+                        Transaction::Deposit { .. } | Transaction::Withdraw { .. }
+                    ),
+                };
+                let client_id = transaction.client_id();
+                let has_account = self.client_accounts.find(client_id)?.is_some();
+                let opens_account = creates_client && !has_account;
+
+                // Journaled when the replay has to open the account too, or
+                // for the decisions of rules, which often fire on the rejected
+                // transaction itself. Only if they apply to an account though.
+                let journaled = opens_account || (!decisions.is_empty() && has_account);
+                if journaled && let Some(journal) = self.journal.as_mut() {
+                    journal.append_decisions(
+                        transaction.clone(),
+                        true,
+                        decisions.clone(),
+                        input,
+                    )?;
+                }
+                if creates_client {
+                    self.get_or_create_client(client_id)?;
+                }
+                if !matches!(err, EngineError::Storage(_)) {
+                    self.fraud.commit(&transaction, Err(&err));
+                }
+                if !decisions.is_empty() {
+                    self.apply_fraud(&decisions)?;
+                    self.fraud.alert(&decisions);
+                }
+                return Err(err);
+            }
         };
 
-        match transaction {
+        // Write-ahead: the transaction has to be durable in the journal
+        // before it touches the repositories
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append_decisions(transaction.clone(), false, decisions.clone(), input)?;
+        }
+        let account = update.account.clone();
+        self.apply(update)?;
+
+        // Rules only learn of it once it's been applied for good
+        self.fraud.commit(&transaction, Ok(&account));
+        self.apply_fraud(&decisions)?;
+        self.fraud.alert(&decisions);
+        Ok(())
+    }
+
+    /// Handles one record of the input, whether or not it could be parsed
+    pub fn handle_record<E: fmt::Display>(&mut self, record: InputRecord<E>) -> Result<(), Reject> {
+        self.handle_record_from(record, None)
+    }
+
+    /// Like `handle_record`, journaling the record's `position` in the
+    /// input so `checkpoint` can tell a resumed run where to pick up
+    pub fn handle_record_at<E: fmt::Display>(
+        &mut self,
+        record: InputRecord<E>,
+        position: InputPosition,
+    ) -> Result<(), Reject> {
+        self.handle_record_from(record, Some(position))
+    }
+
+    fn handle_record_from<E: fmt::Display>(
+        &mut self,
+        record: InputRecord<E>,
+        input: Option<InputPosition>,
+    ) -> Result<(), Reject> {
+        match record.transaction {
+            Ok(transaction) => self
+                .handle_from(transaction, input)
+                .map_err(|err| Reject::rejected(record.source, record.line, record.fields, &err)),
+            Err(err) => Err(Reject::malformed(
+                record.source,
+                record.line,
+                record.fields,
+                &err,
+            )),
+        }
+    }
+
+    /// Handles every record in order, returning the ones that were skipped.
+    ///
+    /// With `Validation::Strict` it stops at the first malformed or
+    /// rejected record instead and returns it as the error. Whatever
+    /// came before it has been applied.
+    pub fn handle_records<E: fmt::Display>(
+        &mut self,
+        records: impl IntoIterator<Item = InputRecord<E>>,
+        validation: Validation,
+    ) -> Result<Vec<Reject>, Reject> {
+        let mut rejects = Vec::new();
+        for record in records {
+            if let Err(reject) = self.handle_record(record) {
+                if validation == Validation::Strict {
+                    return Err(reject);
+                }
+                rejects.push(reject);
+            }
+        }
+        Ok(rejects)
+    }
+
+    fn apply(&mut self, update: Update) -> Result<(), EngineError> {
+        self.client_accounts.save(update.account)?;
+        self.ledger.save(update.transaction_id, update.entry)?;
+        Ok(())
+    }
+
+    // Records the decisions against their accounts, locking them if asked
+    fn apply_fraud(&mut self, decisions: &[FraudDecision]) -> Result<(), EngineError> {
+        for decision in decisions {
+            // Rules can't open accounts
+            let Some(mut client_acc) = self.client_accounts.find(decision.client_id)? else {
+                continue;
+            };
+
+            client_acc.fraud_decisions.push(decision.clone());
+            if decision.action == FraudAction::Lock {
+                client_acc.locked = true;
+            }
+            self.client_accounts.save(client_acc)?;
+        }
+        Ok(())
+    }
+
+    // Validates `transaction` against the current state without writing
+    // anything, locked accounts refusing what `lock_policy` blocks
+    fn prepare(
+        &self,
+        transaction: &Transaction,
+        lock_policy: LockPolicy,
+    ) -> Result<Update, EngineError> {
+        // We only need to track the Deposits and Withdrawals in these usecases,
+        // and only once they've been accepted, otherwise a rejected withdrawal
+        // could later be disputed and credit funds that never left the account.
+        // Administrative operations are kept too, as the record of who
+        // changed the account and why.
+        //
+        // We don't need to store the dispute, chargeback, resolves
+        // plus they dont have a unique ID for the key and generating
+        // one could cause clashes for upcoming transactions. This is
+        // a problem I'd solve given more time
+        let recorded = transaction.is_admin()
+            || matches!(
+                transaction,
+                Transaction::Deposit { .. } | Transaction::Withdraw { .. }
+            );
+        let transaction_id = transaction.transaction_id();
+        if recorded && self.ledger.find(transaction_id)?.is_some() {
+            return Err(EngineError::DuplicateTransaction { transaction_id });
+        }
+
+        match *transaction {
             Transaction::Deposit {
-                client_id, amount, ..
+                transaction_id,
+                client_id,
+                amount,
             } => {
-                let client_acc = self.get_or_create_client(client_id);
+                let mut client_acc = self.find_or_new_client(client_id)?;
+
+                if amount.is_negative() {
+                    return Err(EngineError::NegativeAmount {
+                        transaction_id,
+                        amount,
+                    });
+                }
+
+                if client_acc.closed {
+                    return Err(EngineError::AccountClosed { client_id });
+                }
 
-                if amount < 0.0 {
-                    anyhow::bail!("cannot deposit negative amount");
+                if client_acc.locked && lock_policy.blocks(transaction) {
+                    return Err(EngineError::AccountLocked { client_id });
                 }
-                client_acc.available += amount;
-                Ok(())
+
+                // The total must stay representable too
+                client_acc.available = client_acc
+                    .available
+                    .checked_add(amount)
+                    .filter(|available| available.checked_add(client_acc.held).is_some())
+                    .ok_or(EngineError::Overflow { client_id })?;
+
+                Ok(Update {
+                    account: client_acc,
+                    transaction_id,
+                    entry: LedgerEntry::new(transaction.clone()),
+                })
             }
 
             Transaction::Withdraw {
-                client_id, amount, ..
+                transaction_id,
+                client_id,
+                amount,
             } => {
-                let client_acc = self.get_or_create_client(client_id);
+                let mut client_acc = self.find_or_new_client(client_id)?;
+
+                if amount.is_negative() {
+                    return Err(EngineError::NegativeAmount {
+                        transaction_id,
+                        amount,
+                    });
+                }
 
-                if amount < 0.0 {
-                    anyhow::bail!("cannot withdraw negative amount");
+                if client_acc.closed {
+                    return Err(EngineError::AccountClosed { client_id });
                 }
 
-                if client_acc.locked {
-                    anyhow::bail!("client account {:?} is locked", client_id);
+                if client_acc.locked && lock_policy.blocks(transaction) {
+                    return Err(EngineError::AccountLocked { client_id });
                 }
 
                 if client_acc.available < amount {
-                    anyhow::bail!("client account {:?} does not have enough funds", client_id);
+                    return Err(EngineError::InsufficientFunds {
+                        client_id,
+                        available: client_acc.available,
+                        required: amount,
+                    });
                 }
 
-                client_acc.available -= amount;
-                Ok(())
+                client_acc.available = client_acc
+                    .available
+                    .checked_sub(amount)
+                    .ok_or(EngineError::Overflow { client_id })?;
+
+                Ok(Update {
+                    account: client_acc,
+                    transaction_id,
+                    entry: LedgerEntry::new(transaction.clone()),
+                })
             }
 
             Transaction::Dispute {
                 transaction_id,
                 client_id: dispute_client_id,
-                ..
             } => {
-                // Is a dispute ever valid for a withdrawal???
-                if let Some(Transaction::Deposit {
-                    client_id: transaction_client_id,
-                    amount,
-                    disputed,
-                    ..
-                }) = self.ledger.get_mut(&transaction_id)
-                {
-                    if dispute_client_id != *transaction_client_id {
-                        anyhow::bail!(
-                            "client {} does not have transaction id {}",
-                            dispute_client_id,
-                            *transaction_client_id
-                        );
-                    }
+                let Some(mut entry) = self.ledger.find(transaction_id)? else {
+                    return Err(EngineError::UnknownTransaction { transaction_id });
+                };
+
+                let (transaction_client_id, amount, is_deposit) = match entry.transaction {
+                    Transaction::Deposit {
+                        client_id, amount, ..
+                    } => (client_id, amount, true),
+                    Transaction::Withdraw {
+                        client_id, amount, ..
+                    } => (client_id, amount, false),
+                    // Administrative operations can't be disputed
+                    _ => return Err(EngineError::UnknownTransaction { transaction_id }),
+                };
+
+                if dispute_client_id != transaction_client_id {
+                    return Err(EngineError::ClientMismatch {
+                        transaction_id,
+                        client_id: dispute_client_id,
+                        owner_id: transaction_client_id,
+                    });
+                }
 
-                    if let Some(ClientAccount {
-                        held, available, ..
-                    }) = self.client_accounts.get_mut(transaction_client_id)
-                    {
-                        if available < amount {
-                            anyhow::bail!(
-                                "client {} does not enough funds to dispute",
-                                transaction_client_id
-                            );
-                        }
+                if !entry.state.can_transition_to(DisputeState::Disputed) {
+                    return Err(match entry.state {
+                        DisputeState::Disputed => EngineError::AlreadyDisputed { transaction_id },
+                        state => EngineError::DisputeClosed {
+                            transaction_id,
+                            state,
+                        },
+                    });
+                }
+
+                let Some(mut client_acc) = self.client_accounts.find(transaction_client_id)? else {
+                    // cannot be the fisrt time were seeing this client
+                    return Err(EngineError::UnknownClient {
+                        client_id: dispute_client_id,
+                    });
+                };
+
+                if client_acc.closed {
+                    return Err(EngineError::AccountClosed {
+                        client_id: dispute_client_id,
+                    });
+                }
 
-                        *held += *amount;
-                        *available -= *amount;
-                        *disputed = true;
-                    } else {
-                        // cannot be the fisrt time were seeing this client
-                        self.get_or_create_client(dispute_client_id);
-                        anyhow::bail!(
-                            "could not find client {} for the dispute, creating it",
-                            dispute_client_id
-                        );
+                if client_acc.locked && lock_policy.blocks(transaction) {
+                    return Err(EngineError::AccountLocked {
+                        client_id: dispute_client_id,
+                    });
+                }
+
+                let overflow = EngineError::Overflow {
+                    client_id: dispute_client_id,
+                };
+
+                if is_deposit {
+                    // The deposited funds are held until the dispute is settled
+                    if client_acc.available < amount {
+                        return Err(EngineError::InsufficientFunds {
+                            client_id: dispute_client_id,
+                            available: client_acc.available,
+                            required: amount,
+                        });
                     }
 
-                    return Ok(());
+                    client_acc.held = client_acc
+                        .held
+                        .checked_add(amount)
+                        .ok_or(overflow.clone())?;
+                    client_acc.available =
+                        client_acc.available.checked_sub(amount).ok_or(overflow)?;
+                } else {
+                    // The withdrawn funds are re-credited, but held
+                    client_acc.held = client_acc
+                        .held
+                        .checked_add(amount)
+                        .filter(|held| held.checked_add(client_acc.available).is_some())
+                        .ok_or(overflow)?;
                 }
 
-                anyhow::bail!("disputed transaction {} does not exist", transaction_id);
+                entry.state = DisputeState::Disputed;
+                Ok(Update {
+                    account: client_acc,
+                    transaction_id,
+                    entry,
+                })
             }
 
             Transaction::Resolve {
                 transaction_id,
                 client_id: dispute_client_id,
-                ..
             }
             | Transaction::Chargeback {
                 transaction_id,
                 client_id: dispute_client_id,
-                ..
             } => {
-                if let Some(Transaction::Deposit {
-                    client_id: transaction_client_id,
-                    amount,
-                    disputed,
-                    ..
-                }) = self.ledger.get_mut(&transaction_id)
-                {
-                    if *transaction_client_id != dispute_client_id {
-                        anyhow::bail!(
-                            "client {} does not have transaction id {}",
-                            *transaction_client_id,
-                            dispute_client_id
-                        );
-                    }
+                let Some(mut entry) = self.ledger.find(transaction_id)? else {
+                    return Err(EngineError::UnknownTransaction { transaction_id });
+                };
+
+                let (transaction_client_id, amount, is_deposit) = match entry.transaction {
+                    Transaction::Deposit {
+                        client_id, amount, ..
+                    } => (client_id, amount, true),
+                    Transaction::Withdraw {
+                        client_id, amount, ..
+                    } => (client_id, amount, false),
+                    _ => return Err(EngineError::UnknownTransaction { transaction_id }),
+                };
+
+                if transaction_client_id != dispute_client_id {
+                    return Err(EngineError::ClientMismatch {
+                        transaction_id,
+                        client_id: dispute_client_id,
+                        owner_id: transaction_client_id,
+                    });
+                }
 
-                    if let Some(ClientAccount {
-                        held,
-                        available,
-                        locked,
-                        ..
-                    }) = self.client_accounts.get_mut(transaction_client_id)
-                    {
-                        if !*disputed {
-                            anyhow::bail!("transaction {} has not been disputed", transaction_id);
-                        }
+                let Some(mut client_acc) = self.client_accounts.find(transaction_client_id)? else {
+                    // cannot be the fisrt time were seeing this client
+                    return Err(EngineError::UnknownClient {
+                        client_id: dispute_client_id,
+                    });
+                };
+
+                if client_acc.closed {
+                    return Err(EngineError::AccountClosed {
+                        client_id: dispute_client_id,
+                    });
+                }
 
-                        // These only differ in these operations
-                        if matches!(transaction, Transaction::Resolve { .. }) {
-                            if held < amount {
-                                anyhow::bail!(
-                                    "client {} does not enough held funds to resolve",
-                                    transaction_client_id
-                                );
-                            }
-
-                            *available += *amount;
-                            *held -= *amount;
-                            *disputed = false;
-                        }
+                // Never blocks a chargeback, the bank has already taken the funds back
+                if client_acc.locked && lock_policy.blocks(transaction) {
+                    return Err(EngineError::AccountLocked {
+                        client_id: dispute_client_id,
+                    });
+                }
+
+                let is_resolve = matches!(*transaction, Transaction::Resolve { .. });
+                let next_state = if is_resolve {
+                    DisputeState::Resolved
+                } else {
+                    DisputeState::ChargedBack
+                };
+
+                if !entry.state.can_transition_to(next_state) {
+                    return Err(EngineError::NotDisputed { transaction_id });
+                }
+
+                if client_acc.held < amount {
+                    return Err(EngineError::InsufficientHeldFunds {
+                        client_id: dispute_client_id,
+                        held: client_acc.held,
+                        required: amount,
+                    });
+                }
+
+                let overflow = EngineError::Overflow {
+                    client_id: dispute_client_id,
+                };
+
+                // Either way the held funds are released. A resolved deposit
+                // and a charged back withdrawal return them to the client,
+                // a charged back deposit and a resolved withdrawal don't.
+                if is_resolve == is_deposit {
+                    client_acc.available = client_acc
+                        .available
+                        .checked_add(amount)
+                        .ok_or(overflow.clone())?;
+                }
+                client_acc.held = client_acc.held.checked_sub(amount).ok_or(overflow)?;
+
+                if !is_resolve {
+                    client_acc.locked = true;
+                }
+
+                entry.state = next_state;
+                Ok(Update {
+                    account: client_acc,
+                    transaction_id,
+                    entry,
+                })
+            }
+
+            Transaction::Freeze {
+                transaction_id,
+                client_id,
+                ref operator,
+                ref reason,
+            }
+            | Transaction::Unfreeze {
+                transaction_id,
+                client_id,
+                ref operator,
+                ref reason,
+            }
+            | Transaction::Close {
+                transaction_id,
+                client_id,
+                ref operator,
+                ref reason,
+            } => {
+                if operator.trim().is_empty() || reason.trim().is_empty() {
+                    return Err(EngineError::MissingAuthorization { transaction_id });
+                }
+
+                // Support acts on accounts that exist, it doesn't open them
+                let Some(mut client_acc) = self.client_accounts.find(client_id)? else {
+                    return Err(EngineError::UnknownClient { client_id });
+                };
 
-                        if matches!(transaction, Transaction::Chargeback { .. }) {
-                            *held -= *amount;
-                            *locked = true;
-                            *disputed = false;
+                if client_acc.closed {
+                    return Err(EngineError::AccountClosed { client_id });
+                }
+
+                match *transaction {
+                    Transaction::Freeze { .. } => {
+                        if client_acc.locked {
+                            return Err(EngineError::AccountLocked { client_id });
                         }
-                    } else {
-                        // cannot be the fisrt time were seeing this client
-                        self.get_or_create_client(dispute_client_id);
-                        anyhow::bail!(
-                            "could not find client {} for the dispute, creating it",
-                            dispute_client_id
-                        );
+                        client_acc.locked = true;
+                    }
+                    // Reinstates the account, whether a chargeback or a
+                    // freeze locked it
+                    Transaction::Unfreeze { .. } => {
+                        if !client_acc.locked {
+                            return Err(EngineError::NotLocked { client_id });
+                        }
+                        client_acc.locked = false;
+                    }
+                    _ => {
+                        // Open disputes would have nowhere to settle
+                        if client_acc.held != Money::ZERO {
+                            return Err(EngineError::FundsHeld {
+                                client_id,
+                                held: client_acc.held,
+                            });
+                        }
+                        client_acc.closed = true;
+                        client_acc.locked = true;
                     }
-
-                    return Ok(());
                 }
 
-                anyhow::bail!("transaction");
+                Ok(Update {
+                    account: client_acc,
+                    transaction_id,
+                    entry: LedgerEntry::new(transaction.clone()),
+                })
             }
         }
     }
@@ -210,11 +783,13 @@ impl TransactionEngine {
 mod tests {
     use super::*;
     use anyhow::Context;
+    use fraud::{DepositThenWithdrawal, DepositWithdrawDispute, WithdrawalVelocity};
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn creates_client_after_deposit() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 15.7;
+        let deposit_amount: Money = "15.7".parse()?;
 
         let mut engine = TransactionEngine::default();
 
@@ -222,7 +797,6 @@ mod tests {
             transaction_id: 100,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(transaction)?;
 
@@ -238,7 +812,7 @@ mod tests {
     #[test]
     fn creates_client_after_withdraw() -> anyhow::Result<()> {
         let client_id = 10;
-        let amount = 15.7;
+        let amount: Money = "15.7".parse()?;
 
         let mut engine = TransactionEngine::default();
 
@@ -256,15 +830,15 @@ mod tests {
             .get(&client_id)
             .context("client does not exist")?;
 
-        assert_eq!(client.available, 0.0);
+        assert_eq!(client.available, Money::ZERO);
         Ok(())
     }
 
     #[test]
     fn withdraws_valid_amount() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
-        let withdraw_amount = 50.25;
+        let deposit_amount: Money = "100.5".parse()?;
+        let withdraw_amount: Money = "50.25".parse()?;
 
         let mut engine = TransactionEngine::default();
 
@@ -273,7 +847,6 @@ mod tests {
             transaction_id: 100,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -289,8 +862,11 @@ mod tests {
             .get(&client_id)
             .context("client does not exist")?;
 
-        assert_eq!(client.available, deposit_amount - withdraw_amount);
-        assert_eq!(client.held, 0.0);
+        assert_eq!(
+            Some(client.available),
+            deposit_amount.checked_sub(withdraw_amount)
+        );
+        assert_eq!(client.held, Money::ZERO);
         assert!(!client.locked);
         Ok(())
     }
@@ -298,8 +874,8 @@ mod tests {
     #[test]
     fn withdraws_invalid_amount() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
-        let withdraw_amount = 150.25;
+        let deposit_amount: Money = "100.5".parse()?;
+        let withdraw_amount: Money = "150.25".parse()?;
 
         let mut engine = TransactionEngine::default();
 
@@ -308,7 +884,6 @@ mod tests {
             transaction_id: 100,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -326,7 +901,7 @@ mod tests {
             .context("client does not exist")?;
 
         assert_eq!(client.available, deposit_amount);
-        assert_eq!(client.held, 0.0);
+        assert_eq!(client.held, Money::ZERO);
         assert!(!client.locked);
         Ok(())
     }
@@ -334,8 +909,8 @@ mod tests {
     #[test]
     fn withdraws_ignored_when_locked() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
-        let withdraw_amount = 50.25;
+        let deposit_amount: Money = "100.5".parse()?;
+        let withdraw_amount: Money = "50.25".parse()?;
 
         let mut engine = TransactionEngine::default();
 
@@ -344,7 +919,6 @@ mod tests {
             transaction_id: 100,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -371,7 +945,7 @@ mod tests {
             .context("client does not exist")?;
 
         assert_eq!(client.available, deposit_amount);
-        assert_eq!(client.held, 0.0);
+        assert_eq!(client.held, Money::ZERO);
         assert!(client.locked);
         Ok(())
     }
@@ -379,7 +953,7 @@ mod tests {
     #[test]
     fn disputes_valid_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -389,7 +963,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -406,7 +979,7 @@ mod tests {
     #[test]
     fn disputes_invalid_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -416,7 +989,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -427,15 +999,20 @@ mod tests {
         };
 
         // Expected to fail
-        assert!(engine.handle(dispute).is_err());
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::UnknownTransaction {
+                transaction_id: transaction_id + 1
+            })
+        );
         Ok(())
     }
 
     #[test]
     fn disputes_withdrawn_amount() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
-        let withdraw_amount = 50.0;
+        let deposit_amount: Money = "100.5".parse()?;
+        let withdraw_amount: Money = "50.0".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -445,7 +1022,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -461,20 +1037,304 @@ mod tests {
             transaction_id,
             client_id,
         };
-        assert!(engine.handle(dispute).is_err());
+        assert!(matches!(
+            engine.handle(dispute),
+            Err(EngineError::InsufficientFunds { client_id: 10, .. })
+        ));
+
+        // This is clearly a fraud, but without the
+        // `DepositWithdrawDispute` rule the engine doesn't
+        // assume bad intent, see the test below
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert!(!client_acc.locked);
+
+        Ok(())
+    }
+
+    #[test]
+    fn locks_deposit_withdraw_dispute() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal_path = dir.path().join("journal.jsonl");
+        let client_id = 10;
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let sink = alerts.clone();
+            let mut engine = TransactionEngine::default()
+                .with_fraud_rule(DepositWithdrawDispute::default())
+                .on_fraud_alert(move |decision| sink.lock().unwrap().push(decision.clone()));
+            engine.recover(&journal_path)?;
+
+            engine.handle(Transaction::Deposit {
+                transaction_id: 1,
+                client_id,
+                amount: "100.5".parse()?,
+            })?;
+            engine.handle(Transaction::Withdraw {
+                transaction_id: 2,
+                client_id,
+                amount: "50.0".parse()?,
+            })?;
+
+            // Still rejected, but now it locks the account
+            assert!(matches!(
+                engine.handle(Transaction::Dispute {
+                    transaction_id: 1,
+                    client_id,
+                }),
+                Err(EngineError::InsufficientFunds { .. })
+            ));
+            let client_acc = engine
+                .client_accounts
+                .get(&client_id)
+                .context("client exists")?;
+            assert!(client_acc.locked);
+        }
+
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec![FraudDecision {
+                rule: "deposit_withdraw_dispute".to_string(),
+                action: FraudAction::Lock,
+                client_id,
+                transaction_id: 1,
+            }]
+        );
+
+        // The lock outlives a restart even though the dispute was rejected
+        let mut engine = TransactionEngine::default();
+        assert_eq!(engine.recover(&journal_path)?, 3);
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert!(client_acc.locked);
+        assert_eq!(client_acc.available, "50.5".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_and_records_fraud_decisions() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal_path = dir.path().join("journal.jsonl");
+        let client_id = 3;
+        let withdraw = |transaction_id, amount: &str| -> anyhow::Result<Transaction> {
+            Ok(Transaction::Withdraw {
+                transaction_id,
+                client_id,
+                amount: amount.parse()?,
+            })
+        };
+
+        {
+            let mut engine = TransactionEngine::default()
+                .with_fraud_rule(WithdrawalVelocity::new(
+                    "10.0".parse()?,
+                    5,
+                    FraudAction::Reject,
+                ))
+                .with_fraud_rule(DepositThenWithdrawal::new(FraudAction::Flag));
+            engine.recover(&journal_path)?;
+
+            engine.handle(Transaction::Deposit {
+                transaction_id: 1,
+                client_id,
+                amount: "20.0".parse()?,
+            })?;
+            engine.handle(withdraw(2, "8.0")?)?;
+            assert_eq!(
+                engine.handle(withdraw(3, "3.0")?),
+                Err(EngineError::FraudSuspected {
+                    client_id,
+                    rule: "withdrawal_velocity".to_string(),
+                })
+            );
+
+            // Within the limit, but all of what was just deposited
+            engine.handle(Transaction::Deposit {
+                transaction_id: 4,
+                client_id,
+                amount: "2.0".parse()?,
+            })?;
+            engine.handle(withdraw(5, "2.0")?)?;
+
+            // The refused withdrawal is left out of the ledger
+            assert!(!engine.ledger.contains_key(&3));
+        }
+
+        // Replayed from the journal along with the decisions
+        let mut engine = TransactionEngine::default();
+        engine.recover(&journal_path)?;
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert_eq!(client_acc.available, "12.0".parse()?);
+        let decisions: Vec<_> = client_acc
+            .fraud_decisions
+            .iter()
+            .map(|decision| {
+                (
+                    decision.rule.as_str(),
+                    decision.action,
+                    decision.transaction_id,
+                )
+            })
+            .collect();
+        assert_eq!(
+            decisions,
+            vec![
+                ("withdrawal_velocity", FraudAction::Reject, 3),
+                ("deposit_then_withdrawal", FraudAction::Flag, 5),
+            ]
+        );
+        assert!(!client_acc.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn rules_only_count_applied_transactions() -> anyhow::Result<()> {
+        let client_id = 4;
+        let mut engine = TransactionEngine::default()
+            .with_fraud_rule(WithdrawalVelocity::new(
+                "45.0".parse()?,
+                5,
+                FraudAction::Flag,
+            ))
+            .with_fraud_rule(DepositThenWithdrawal::new(FraudAction::Reject));
+
+        engine.handle(Transaction::Deposit {
+            transaction_id: 1,
+            client_id,
+            amount: "40.0".parse()?,
+        })?;
+        assert_eq!(
+            engine.handle(Transaction::Withdraw {
+                transaction_id: 2,
+                client_id,
+                amount: "40.0".parse()?,
+            }),
+            Err(EngineError::FraudSuspected {
+                client_id,
+                rule: "deposit_then_withdrawal".to_string(),
+            })
+        );
+
+        // The rejected withdrawal never counted towards the velocity limit
+        engine.handle(Transaction::Withdraw {
+            transaction_id: 3,
+            client_id,
+            amount: "10.0".parse()?,
+        })?;
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert_eq!(client_acc.fraud_flags(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn flags_without_locking() -> anyhow::Result<()> {
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let sink = alerts.clone();
+        let mut engine = TransactionEngine::default()
+            .with_fraud_rule(DepositWithdrawDispute {
+                action: FraudAction::Flag,
+            })
+            .on_fraud_alert(move |decision| sink.lock().unwrap().push(decision.clone()));
+
+        engine.handle(Transaction::Deposit {
+            transaction_id: 1,
+            client_id: 1,
+            amount: "1.0".parse()?,
+        })?;
+        engine.handle(Transaction::Withdraw {
+            transaction_id: 2,
+            client_id: 1,
+            amount: "1.0".parse()?,
+        })?;
+        engine
+            .handle(Transaction::Dispute {
+                transaction_id: 1,
+                client_id: 1,
+            })
+            .unwrap_err();
+
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+        assert!(
+            !engine
+                .client_accounts
+                .get(&1)
+                .context("client exists")?
+                .locked
+        );
+        Ok(())
+    }
+
+    // Takes its action against every transaction
+    #[derive(Debug)]
+    struct EveryTransaction(FraudAction);
 
-        // TODO : This is clearly a fraud, but I'm unsure
-        // if this is part of the assignment. This should
-        // result in the account being locked but curent
-        // implementation doesnt assume bad intent.
+    impl FraudRule for EveryTransaction {
+        fn name(&self) -> &str {
+            "every_transaction"
+        }
+
+        fn check(
+            &self,
+            _: &Transaction,
+            _: Result<&ClientAccount, &EngineError>,
+        ) -> Option<FraudAction> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn replays_decisions_on_accounts_rejections_opened() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        {
+            let mut engine =
+                TransactionEngine::default().with_fraud_rule(EveryTransaction(FraudAction::Lock));
+            engine.recover(&path)?;
+
+            // Opens the account it's rejected on
+            let withdrawal = engine.handle(Transaction::Withdraw {
+                transaction_id: 1,
+                client_id: 7,
+                amount: "1.0".parse()?,
+            });
+            assert!(withdrawal.is_err());
+
+            // Opens none, so there's nothing to replay
+            let freeze = engine.handle(Transaction::Freeze {
+                transaction_id: 2,
+                client_id: 8,
+                operator: "alice".to_string(),
+                reason: "testing".to_string(),
+            });
+            assert_eq!(freeze, Err(EngineError::UnknownClient { client_id: 8 }));
+            assert_eq!(engine.journal().map(Journal::last_sequence), Some(1));
+        }
 
+        let mut engine = TransactionEngine::default();
+        assert_eq!(engine.recover(&path)?, 1);
+        let client_acc = engine.client_accounts.get(&7).context("client 7 exists")?;
+        assert!(client_acc.locked);
+        assert_eq!(client_acc.fraud_flags(), 1);
+        assert!(!engine.client_accounts.contains_key(&8));
         Ok(())
     }
 
     #[test]
     fn resolves_valid_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -484,7 +1344,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -506,7 +1365,7 @@ mod tests {
     #[test]
     fn resolves_undisputed_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -516,7 +1375,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -524,7 +1382,10 @@ mod tests {
             transaction_id,
             client_id,
         };
-        assert!(engine.handle(resolve).is_err());
+        assert_eq!(
+            engine.handle(resolve),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
 
         Ok(())
     }
@@ -532,7 +1393,7 @@ mod tests {
     #[test]
     fn chargeback_valid_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -542,7 +1403,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -576,7 +1436,7 @@ mod tests {
     #[test]
     fn chargeback_undisputed_transaction() -> anyhow::Result<()> {
         let client_id = 10;
-        let deposit_amount = 100.5;
+        let deposit_amount: Money = "100.5".parse()?;
         let transaction_id = 100;
 
         let mut engine = TransactionEngine::default();
@@ -586,7 +1446,6 @@ mod tests {
             transaction_id,
             client_id,
             amount: deposit_amount,
-            disputed: false,
         };
         engine.handle(deposit)?;
 
@@ -599,7 +1458,10 @@ mod tests {
             transaction_id,
             client_id,
         };
-        assert!(engine.handle(chargeback).is_err());
+        assert_eq!(
+            engine.handle(chargeback),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
 
         {
             let client = engine.client_accounts.get(&client_id).unwrap();
@@ -608,4 +1470,1030 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn rejects_duplicate_transaction() -> anyhow::Result<()> {
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id: 10,
+            amount: "1.0".parse()?,
+        };
+        engine.handle(deposit.clone())?;
+
+        assert_eq!(
+            engine.handle(deposit),
+            Err(EngineError::DuplicateTransaction {
+                transaction_id: 100
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_negative_amounts() -> anyhow::Result<()> {
+        let amount: Money = "-1.0".parse()?;
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id: 10,
+            amount,
+        };
+        assert_eq!(
+            engine.handle(deposit),
+            Err(EngineError::NegativeAmount {
+                transaction_id: 100,
+                amount
+            })
+        );
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id: 101,
+            client_id: 10,
+            amount,
+        };
+        assert_eq!(
+            engine.handle(withdraw),
+            Err(EngineError::NegativeAmount {
+                transaction_id: 101,
+                amount
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_other_clients_transaction() -> anyhow::Result<()> {
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id: 10,
+            amount: "1.0".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id: 100,
+            client_id: 11,
+        };
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::ClientMismatch {
+                transaction_id: 100,
+                client_id: 11,
+                owner_id: 10
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_already_disputed_transaction() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 100;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute.clone())?;
+
+        // Holding the funds twice would be wrong
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::AlreadyDisputed { transaction_id })
+        );
+
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.held, "100.5".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_valid_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        engine.handle(withdraw)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute)?;
+
+        // Withdrawn funds come back, but held
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.available, "50.25".parse()?);
+        assert_eq!(client.held, "50.25".parse()?);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_rejected_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        // Not enough funds, so nothing was withdrawn
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        assert!(engine.handle(withdraw).is_err());
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::UnknownTransaction { transaction_id })
+        );
+
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.held, Money::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_valid_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        engine.handle(withdraw)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute)?;
+
+        let resolve = Transaction::Resolve {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(resolve)?;
+
+        // The withdrawal stands, the held funds are released
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.available, "50.25".parse()?);
+        assert_eq!(client.held, Money::ZERO);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_undisputed_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        engine.handle(withdraw)?;
+
+        let resolve = Transaction::Resolve {
+            transaction_id,
+            client_id,
+        };
+        assert_eq!(
+            engine.handle(resolve),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_valid_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        engine.handle(withdraw)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute)?;
+
+        let chargeback = Transaction::Chargeback {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(chargeback)?;
+
+        // The withdrawal is reversed and the account locked
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.available, "100.5".parse()?);
+        assert_eq!(client.held, Money::ZERO);
+        assert!(client.locked);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_undisputed_withdrawal() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 101;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let withdraw = Transaction::Withdraw {
+            transaction_id,
+            client_id,
+            amount: "50.25".parse()?,
+        };
+        engine.handle(withdraw)?;
+
+        let chargeback = Transaction::Chargeback {
+            transaction_id,
+            client_id,
+        };
+        assert_eq!(
+            engine.handle(chargeback),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
+
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert!(!client.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn tracks_dispute_lifecycle() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 100;
+
+        let mut engine = TransactionEngine::default();
+        let state =
+            |engine: &TransactionEngine| engine.ledger.get(&transaction_id).map(|e| e.state);
+
+        let deposit = Transaction::Deposit {
+            transaction_id,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+        assert_eq!(state(&engine), Some(DisputeState::Settled));
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute)?;
+        assert_eq!(state(&engine), Some(DisputeState::Disputed));
+
+        let chargeback = Transaction::Chargeback {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(chargeback)?;
+        assert_eq!(state(&engine), Some(DisputeState::ChargedBack));
+
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_resolved_transaction() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 100;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute.clone())?;
+
+        let resolve = Transaction::Resolve {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(resolve.clone())?;
+
+        // A resolved dispute can't be reopened
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::DisputeClosed {
+                transaction_id,
+                state: DisputeState::Resolved
+            })
+        );
+        assert_eq!(
+            engine.handle(resolve),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
+
+        let client = engine.client_accounts.get(&client_id).unwrap();
+        assert_eq!(client.available, "100.5".parse()?);
+        assert_eq!(client.held, Money::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn disputes_charged_back_transaction() -> anyhow::Result<()> {
+        let client_id = 10;
+        let transaction_id = 100;
+
+        let mut engine = TransactionEngine::default();
+
+        let deposit = Transaction::Deposit {
+            transaction_id,
+            client_id,
+            amount: "100.5".parse()?,
+        };
+        engine.handle(deposit)?;
+
+        let dispute = Transaction::Dispute {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(dispute.clone())?;
+
+        let chargeback = Transaction::Chargeback {
+            transaction_id,
+            client_id,
+        };
+        engine.handle(chargeback.clone())?;
+
+        assert_eq!(
+            engine.handle(dispute),
+            Err(EngineError::DisputeClosed {
+                transaction_id,
+                state: DisputeState::ChargedBack
+            })
+        );
+        assert_eq!(
+            engine.handle(chargeback),
+            Err(EngineError::NotDisputed { transaction_id })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lock_policy_blocks_chosen_kinds() -> anyhow::Result<()> {
+        let client_id = 6;
+        let locked_engine = |lock_policy| -> anyhow::Result<TransactionEngine> {
+            let mut engine = TransactionEngine::default().with_lock_policy(lock_policy);
+            for transaction_id in 1..=3 {
+                engine.handle(Transaction::Deposit {
+                    transaction_id,
+                    client_id,
+                    amount: "1.0".parse()?,
+                })?;
+            }
+            engine.handle(Transaction::Dispute {
+                transaction_id: 1,
+                client_id,
+            })?;
+            engine.handle(Transaction::Dispute {
+                transaction_id: 2,
+                client_id,
+            })?;
+            engine.handle(Transaction::Chargeback {
+                transaction_id: 1,
+                client_id,
+            })?;
+            Ok(engine)
+        };
+        let deposit = Transaction::Deposit {
+            transaction_id: 4,
+            client_id,
+            amount: "1.0".parse()?,
+        };
+        let dispute = Transaction::Dispute {
+            transaction_id: 3,
+            client_id,
+        };
+        let resolve = Transaction::Resolve {
+            transaction_id: 2,
+            client_id,
+        };
+        let locked = Err(EngineError::AccountLocked { client_id });
+
+        // Only withdrawals by default
+        let mut engine = locked_engine(LockPolicy::default())?;
+        engine.handle(deposit.clone())?;
+        engine.handle(dispute.clone())?;
+        engine.handle(resolve.clone())?;
+
+        let mut engine = locked_engine(LockPolicy::ALL)?;
+        assert_eq!(engine.handle(deposit.clone()), locked);
+        assert_eq!(engine.handle(dispute.clone()), locked);
+        assert_eq!(engine.handle(resolve), locked);
+
+        // A chargeback still settles a dispute opened before the lock
+        engine.handle(Transaction::Chargeback {
+            transaction_id: 2,
+            client_id,
+        })?;
+
+        let mut engine = locked_engine(LockPolicy::NONE)?;
+        engine.handle(Transaction::Withdraw {
+            transaction_id: 5,
+            client_id,
+            amount: "1.0".parse()?,
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn unfreezes_charged_back_account() -> anyhow::Result<()> {
+        let client_id = 4;
+        let mut engine = TransactionEngine::default();
+
+        engine.handle(Transaction::Deposit {
+            transaction_id: 1,
+            client_id,
+            amount: "10.0".parse()?,
+        })?;
+        engine.handle(Transaction::Deposit {
+            transaction_id: 2,
+            client_id,
+            amount: "3.0".parse()?,
+        })?;
+        engine.handle(Transaction::Dispute {
+            transaction_id: 1,
+            client_id,
+        })?;
+        engine.handle(Transaction::Chargeback {
+            transaction_id: 1,
+            client_id,
+        })?;
+
+        let unfreeze = Transaction::Unfreeze {
+            transaction_id: 3,
+            client_id,
+            operator: "alice".to_string(),
+            reason: "fraud review #12 cleared".to_string(),
+        };
+        engine.handle(unfreeze.clone())?;
+
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert!(!client_acc.locked);
+        assert_eq!(
+            engine.ledger.get(&3),
+            Some(&LedgerEntry::new(unfreeze.clone()))
+        );
+
+        // Withdrawals go through again, and the unfreeze is never undone
+        engine.handle(Transaction::Withdraw {
+            transaction_id: 4,
+            client_id,
+            amount: "1.0".parse()?,
+        })?;
+        assert_eq!(
+            engine.handle(unfreeze),
+            Err(EngineError::DuplicateTransaction { transaction_id: 3 })
+        );
+        assert_eq!(
+            engine.handle(Transaction::Dispute {
+                transaction_id: 3,
+                client_id,
+            }),
+            Err(EngineError::UnknownTransaction { transaction_id: 3 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn freezes_and_closes_accounts() -> anyhow::Result<()> {
+        let client_id = 5;
+        let mut engine = TransactionEngine::default();
+        let admin = |transaction_id, kind: &str| {
+            let (operator, reason) = ("bob".to_string(), "support ticket".to_string());
+            match kind {
+                "freeze" => Transaction::Freeze {
+                    transaction_id,
+                    client_id,
+                    operator,
+                    reason,
+                },
+                "unfreeze" => Transaction::Unfreeze {
+                    transaction_id,
+                    client_id,
+                    operator,
+                    reason,
+                },
+                _ => Transaction::Close {
+                    transaction_id,
+                    client_id,
+                    operator,
+                    reason,
+                },
+            }
+        };
+
+        // Support doesn't open accounts
+        assert_eq!(
+            engine.handle(admin(1, "freeze")),
+            Err(EngineError::UnknownClient { client_id })
+        );
+        assert!(engine.client_accounts.is_empty());
+
+        engine.handle(Transaction::Deposit {
+            transaction_id: 2,
+            client_id,
+            amount: "2.0".parse()?,
+        })?;
+        assert_eq!(
+            engine.handle(admin(3, "unfreeze")),
+            Err(EngineError::NotLocked { client_id })
+        );
+
+        engine.handle(admin(4, "freeze"))?;
+        assert_eq!(
+            engine.handle(admin(5, "freeze")),
+            Err(EngineError::AccountLocked { client_id })
+        );
+        assert_eq!(
+            engine.handle(Transaction::Withdraw {
+                transaction_id: 6,
+                client_id,
+                amount: "1.0".parse()?,
+            }),
+            Err(EngineError::AccountLocked { client_id })
+        );
+
+        // Held funds have to be settled first
+        engine.handle(Transaction::Dispute {
+            transaction_id: 2,
+            client_id,
+        })?;
+        assert_eq!(
+            engine.handle(admin(7, "close")),
+            Err(EngineError::FundsHeld {
+                client_id,
+                held: "2.0".parse()?
+            })
+        );
+        engine.handle(Transaction::Resolve {
+            transaction_id: 2,
+            client_id,
+        })?;
+        engine.handle(admin(8, "close"))?;
+
+        let client_acc = engine
+            .client_accounts
+            .get(&client_id)
+            .context("client exists")?;
+        assert!(client_acc.closed);
+        assert!(client_acc.locked);
+        assert_eq!(client_acc.available, "2.0".parse()?);
+
+        assert_eq!(
+            engine.handle(Transaction::Deposit {
+                transaction_id: 9,
+                client_id,
+                amount: "1.0".parse()?,
+            }),
+            Err(EngineError::AccountClosed { client_id })
+        );
+        assert_eq!(
+            engine.handle(admin(10, "unfreeze")),
+            Err(EngineError::AccountClosed { client_id })
+        );
+
+        // A bad amount is reported first, whichever way it moves funds
+        let amount: Money = "-1.0".parse()?;
+        assert_eq!(
+            engine.handle(Transaction::Deposit {
+                transaction_id: 11,
+                client_id,
+                amount,
+            }),
+            Err(EngineError::NegativeAmount {
+                transaction_id: 11,
+                amount
+            })
+        );
+        assert_eq!(
+            engine.handle(Transaction::Withdraw {
+                transaction_id: 12,
+                client_id,
+                amount,
+            }),
+            Err(EngineError::NegativeAmount {
+                transaction_id: 12,
+                amount
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_admin_operations_without_authorization() -> anyhow::Result<()> {
+        let mut engine = TransactionEngine::default();
+        engine.handle(Transaction::Deposit {
+            transaction_id: 1,
+            client_id: 1,
+            amount: "1.0".parse()?,
+        })?;
+
+        for (operator, reason) in [("", "fraud review"), ("alice", " ")] {
+            assert_eq!(
+                engine.handle(Transaction::Freeze {
+                    transaction_id: 2,
+                    client_id: 1,
+                    operator: operator.to_string(),
+                    reason: reason.to_string(),
+                }),
+                Err(EngineError::MissingAuthorization { transaction_id: 2 })
+            );
+        }
+        assert!(
+            !engine
+                .client_accounts
+                .get(&1)
+                .context("client exists")?
+                .locked
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn restores_snapshot_and_journal_tail() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let snapshot_path = dir.path().join("snapshot.json");
+        let journal_path = dir.path().join("journal.jsonl");
+
+        {
+            let mut engine = TransactionEngine::default();
+            assert_eq!(engine.restore(&snapshot_path, &journal_path)?, 0);
+
+            engine.handle(Transaction::Deposit {
+                transaction_id: 1,
+                client_id: 1,
+                amount: "5.0".parse()?,
+            })?;
+            engine.handle(Transaction::Dispute {
+                transaction_id: 1,
+                client_id: 1,
+            })?;
+            // Committing alone leaves the snapshot be
+            engine.commit(&InputPosition::default())?;
+            assert!(!snapshot_path.exists());
+            engine.save_snapshot()?;
+
+            // Only in the journal
+            engine.handle(Transaction::Chargeback {
+                transaction_id: 1,
+                client_id: 1,
+            })?;
+        }
+
+        let snapshot = Snapshot::load(&snapshot_path)?.context("snapshot was written")?;
+        assert_eq!(snapshot.sequence, 2);
+
+        let mut engine = TransactionEngine::default();
+        assert_eq!(engine.restore(&snapshot_path, &journal_path)?, 1);
+
+        let client_acc = engine.client_accounts.get(&1).context("client 1 exists")?;
+        assert_eq!(client_acc.available, Money::ZERO);
+        assert_eq!(client_acc.held, Money::ZERO);
+        assert!(client_acc.locked);
+        assert_eq!(
+            engine.ledger.get(&1).map(|entry| entry.state),
+            Some(DisputeState::ChargedBack)
+        );
+        Ok(())
+    }
+
+    // A ledger that is always unavailable
+    struct OfflineLedger;
+
+    impl LedgerRepository for OfflineLedger {
+        fn find(&self, _: u64) -> Result<Option<LedgerEntry>, StorageError> {
+            Err(StorageError::new("offline"))
+        }
+
+        fn save(&mut self, _: u64, _: LedgerEntry) -> Result<(), StorageError> {
+            Err(StorageError::new("offline"))
+        }
+
+        fn all(&self) -> Result<Vec<(u64, LedgerEntry)>, StorageError> {
+            Err(StorageError::new("offline"))
+        }
+    }
+
+    #[test]
+    fn recovers_from_journal() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        {
+            let mut engine = TransactionEngine::default();
+            assert_eq!(engine.recover(&path)?, 0);
+
+            engine.handle(Transaction::Deposit {
+                transaction_id: 1,
+                client_id: 1,
+                amount: "5.0".parse()?,
+            })?;
+            engine.handle(Transaction::Withdraw {
+                transaction_id: 2,
+                client_id: 1,
+                amount: "2.0".parse()?,
+            })?;
+            engine.handle(Transaction::Dispute {
+                transaction_id: 2,
+                client_id: 1,
+            })?;
+
+            // Rejections are only journaled when they open an account
+            let rejected = engine.handle(Transaction::Withdraw {
+                transaction_id: 3,
+                client_id: 1,
+                amount: "10.0".parse()?,
+            });
+            assert!(rejected.is_err());
+            assert_eq!(engine.journal().map(Journal::last_sequence), Some(3));
+        }
+
+        let mut engine = TransactionEngine::default();
+        assert_eq!(engine.recover(&path)?, 3);
+
+        let client_acc = engine.client_accounts.get(&1).context("client 1 exists")?;
+        assert_eq!(client_acc.available, "3.0".parse()?);
+        assert_eq!(client_acc.held, "2.0".parse()?);
+        assert_eq!(
+            engine.ledger.get(&2).map(|entry| entry.state),
+            Some(DisputeState::Disputed)
+        );
+
+        // Carries on journaling after the recovered entries
+        engine.handle(Transaction::Resolve {
+            transaction_id: 2,
+            client_id: 1,
+        })?;
+        assert_eq!(engine.journal().map(Journal::last_sequence), Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn recovers_accounts_opened_by_rejections() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        let accounts = |engine: &TransactionEngine| {
+            let mut accounts = engine.client_accounts.values().cloned().collect::<Vec<_>>();
+            accounts.sort_by_key(|account| account.client_id);
+            accounts
+        };
+        let crashed = {
+            let mut engine = TransactionEngine::default();
+            engine.recover(&path)?;
+            for transaction in [
+                Transaction::Withdraw {
+                    transaction_id: 1,
+                    client_id: 1,
+                    amount: "1.0".parse()?,
+                },
+                Transaction::Deposit {
+                    transaction_id: 2,
+                    client_id: 2,
+                    amount: "-1.0".parse()?,
+                },
+                Transaction::Dispute {
+                    transaction_id: 3,
+                    client_id: 3,
+                },
+            ] {
+                assert!(engine.handle(transaction).is_err());
+            }
+            accounts(&engine)
+        };
+        assert_eq!(crashed.len(), 2);
+
+        let mut engine = TransactionEngine::default();
+        assert_eq!(engine.recover(&path)?, 2);
+        assert_eq!(accounts(&engine), crashed);
+        Ok(())
+    }
+
+    #[test]
+    fn journals_input_positions() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        let csv = "type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,100
+dispute,1,3,";
+        let deposit = InputPosition {
+            record: 1,
+            source: None,
+            line: 2,
+        };
+        {
+            let mut engine = TransactionEngine::default();
+            engine.recover(&path)?;
+            assert_eq!(engine.checkpoint()?, InputPosition::default());
+            for (position, record) in input::read_csv(csv.as_bytes()).enumerate() {
+                // Only the deposit gets journaled
+                let position = record.position(position as u64 + 1);
+                let _ = engine.handle_record_at(record, position);
+            }
+            assert_eq!(engine.checkpoint()?, deposit);
+        }
+
+        let entries = Journal::entries(&path)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries[0].input, Some(deposit.clone()));
+
+        let mut engine = TransactionEngine::default();
+        engine.recover(&path)?;
+        assert_eq!(engine.checkpoint()?, deposit);
+
+        // Transactions that didn't come from an input leave it be
+        engine.handle(Transaction::Deposit {
+            transaction_id: 4,
+            client_id: 1,
+            amount: "1".parse()?,
+        })?;
+        assert_eq!(Journal::open(&path)?.last_input(), Some(&deposit));
+        Ok(())
+    }
+
+    #[test]
+    fn recovers_under_another_lock_policy() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+
+        {
+            let mut engine = TransactionEngine::default();
+            engine.recover(&path)?;
+            engine.handle(Transaction::Deposit {
+                transaction_id: 1,
+                client_id: 1,
+                amount: "5.0".parse()?,
+            })?;
+            engine.handle(Transaction::Dispute {
+                transaction_id: 1,
+                client_id: 1,
+            })?;
+            engine.handle(Transaction::Chargeback {
+                transaction_id: 1,
+                client_id: 1,
+            })?;
+
+            // Fine under the default policy
+            engine.handle(Transaction::Deposit {
+                transaction_id: 2,
+                client_id: 1,
+                amount: "3.0".parse()?,
+            })?;
+        }
+
+        let mut engine = TransactionEngine::default().with_lock_policy(LockPolicy::ALL);
+        assert_eq!(engine.recover(&path)?, 4);
+        let client_acc = engine.client_accounts.get(&1).context("client 1 exists")?;
+        assert_eq!(client_acc.available, "3.0".parse()?);
+        assert!(client_acc.locked);
+
+        // New transactions do go by the new policy
+        let deposit = engine.handle(Transaction::Deposit {
+            transaction_id: 3,
+            client_id: 1,
+            amount: "1.0".parse()?,
+        });
+        assert_eq!(deposit, Err(EngineError::AccountLocked { client_id: 1 }));
+        Ok(())
+    }
+
+    #[test]
+    fn surfaces_repository_errors() -> anyhow::Result<()> {
+        let mut engine = TransactionEngine::new(AccountStore::new(), OfflineLedger);
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 100,
+            client_id: 10,
+            amount: "1.0".parse()?,
+        };
+        assert_eq!(
+            engine.handle(deposit),
+            Err(EngineError::Storage(StorageError::new("offline")))
+        );
+        assert!(engine.client_accounts.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn stops_at_first_invalid_record_when_strict() -> anyhow::Result<()> {
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 5
+withdrawal, 1, 2, 9
+oops, 1
+deposit, 1, 3, 1";
+
+        let mut lenient = TransactionEngine::default();
+        let rejects =
+            lenient.handle_records(input::read_csv(csv.as_bytes()), Validation::Lenient)?;
+        let codes = rejects.iter().map(|reject| reject.code).collect::<Vec<_>>();
+        assert_eq!(codes, vec!["insufficient_funds", "malformed"]);
+        assert_eq!(lenient.client_accounts[&1].available, "6".parse()?);
+
+        let mut strict = TransactionEngine::default();
+        let reject = strict
+            .handle_records(input::read_csv(csv.as_bytes()), Validation::Strict)
+            .err()
+            .context("strict run fails")?;
+        assert_eq!(reject.line, 3);
+        assert_eq!(reject.code, "insufficient_funds");
+        assert_eq!(
+            reject.to_string(),
+            "line 3 (insufficient_funds): client account 1 has 5.0000 available but needs 9.0000"
+        );
+        assert_eq!(strict.client_accounts[&1].available, "5".parse()?);
+        Ok(())
+    }
 }
@@ -0,0 +1,210 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+// Number of decimal places every monetary value is kept to
+const DECIMAL_PLACES: usize = 4;
+const SCALE: i64 = 10_i64.pow(DECIMAL_PLACES as u32);
+
+/// Exact fixed-point monetary amount with four decimal places.
+///
+/// Stored as a signed count of ten-thousandths, so sums of
+/// deposits and withdrawals never drift the way `f64` does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMoneyError(String);
+
+impl fmt::Display for ParseMoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid monetary amount {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMoneyError {}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    /// Builds a value from a raw count of ten-thousandths
+    pub const fn from_raw(raw: i64) -> Self {
+        Money(raw)
+    }
+
+    /// Raw count of ten-thousandths
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseMoneyError(s.to_string());
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(error());
+        }
+
+        // Only plain ASCII digits, no exponents or separators
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if !all_digits(whole) || !all_digits(fraction) {
+            return Err(error());
+        }
+
+        // Never round, an amount we can't store exactly is invalid
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.len() > DECIMAL_PLACES {
+            return Err(error());
+        }
+
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| error())?
+        };
+        let fraction: i64 = format!("{:0<width$}", fraction, width = DECIMAL_PLACES)
+            .parse()
+            .map_err(|_| error())?;
+
+        let raw = whole
+            .checked_mul(SCALE)
+            .and_then(|v| v.checked_add(fraction))
+            .ok_or_else(error)?;
+
+        Ok(Money(if negative { -raw } else { raw }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let raw = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            raw / scale,
+            raw % scale,
+            width = DECIMAL_PLACES
+        )
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+struct MoneyVisitor;
+
+impl Visitor<'_> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount with at most {} places", DECIMAL_PLACES)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Money, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Money, E> {
+        i64::try_from(v)
+            .ok()
+            .and_then(|v| v.checked_mul(SCALE))
+            .map(Money)
+            .ok_or_else(|| E::custom(ParseMoneyError(v.to_string())))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Money, E> {
+        v.checked_mul(SCALE)
+            .map(Money)
+            .ok_or_else(|| E::custom(ParseMoneyError(v.to_string())))
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_exact_decimals() -> anyhow::Result<()> {
+        assert_eq!("1".parse::<Money>()?, Money::from_raw(10_000));
+        assert_eq!("1.5".parse::<Money>()?, Money::from_raw(15_000));
+        assert_eq!("0.0001".parse::<Money>()?, Money::from_raw(1));
+        assert_eq!(".25".parse::<Money>()?, Money::from_raw(2_500));
+        assert_eq!("-2.10".parse::<Money>()?, Money::from_raw(-21_000));
+        assert_eq!("3.14150000".parse::<Money>()?, Money::from_raw(31_415));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_decimals() {
+        for input in ["", ".", "abc", "1.00001", "1e5", "1,5", "--1", "1.2.3"] {
+            assert!(input.parse::<Money>().is_err(), "{:?} parsed", input);
+        }
+        assert!("922337203685478".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn displays_four_decimal_places() -> anyhow::Result<()> {
+        assert_eq!("1.5".parse::<Money>()?.to_string(), "1.5000");
+        assert_eq!("-0.0001".parse::<Money>()?.to_string(), "-0.0001");
+        assert_eq!(Money::ZERO.to_string(), "0.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn sums_without_drift() -> anyhow::Result<()> {
+        let tenth = "0.1".parse::<Money>()?;
+        let mut total = Money::ZERO;
+        for _ in 0..10 {
+            total = total.checked_add(tenth).unwrap();
+        }
+        assert_eq!(total, "1".parse::<Money>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn checked_arithmetic_detects_overflow() {
+        let max = Money::from_raw(i64::MAX);
+        assert_eq!(max.checked_add(Money::from_raw(1)), None);
+        assert_eq!(Money::from_raw(i64::MIN).checked_sub(max), None);
+    }
+}
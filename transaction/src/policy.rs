@@ -0,0 +1,157 @@
+use crate::Transaction;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which transactions a locked account refuses, with `AccountLocked`.
+///
+/// By default only withdrawals are refused. Chargebacks always go
+/// through, as do the administrative operations which have their own
+/// rules about locked accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockPolicy {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub disputes: bool,
+    pub resolves: bool,
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        LockPolicy {
+            withdrawals: true,
+            ..LockPolicy::NONE
+        }
+    }
+}
+
+impl LockPolicy {
+    /// Locked accounts take everything
+    pub const NONE: LockPolicy = LockPolicy {
+        deposits: false,
+        withdrawals: false,
+        disputes: false,
+        resolves: false,
+    };
+
+    /// Locked accounts take nothing but chargebacks
+    pub const ALL: LockPolicy = LockPolicy {
+        deposits: true,
+        withdrawals: true,
+        disputes: true,
+        resolves: true,
+    };
+
+    /// Names of the kinds, as `FromStr` and `Display` write them
+    pub const KINDS: &[&str] = &["deposits", "withdrawals", "disputes", "resolves"];
+
+    /// Whether a locked account refuses `transaction`
+    pub fn blocks(&self, transaction: &Transaction) -> bool {
+        match transaction {
+            Transaction::Deposit { .. } => self.deposits,
+            Transaction::Withdraw { .. } => self.withdrawals,
+            Transaction::Dispute { .. } => self.disputes,
+            Transaction::Resolve { .. } => self.resolves,
+            _ => false,
+        }
+    }
+
+    fn kind_mut(&mut self, kind: &str) -> Option<&mut bool> {
+        match kind.to_ascii_lowercase().as_str() {
+            "deposits" | "deposit" => Some(&mut self.deposits),
+            "withdrawals" | "withdrawal" | "withdraw" => Some(&mut self.withdrawals),
+            "disputes" | "dispute" => Some(&mut self.disputes),
+            "resolves" | "resolve" => Some(&mut self.resolves),
+            _ => None,
+        }
+    }
+}
+
+/// A kind that isn't one of `LockPolicy::KINDS`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKind(pub String);
+
+impl fmt::Display for UnknownKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown transaction kind `{}`, expected one of {}, all or none",
+            self.0,
+            LockPolicy::KINDS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownKind {}
+
+// A comma separated list of the blocked kinds, e.g. `deposits,withdrawals`,
+// or `all` or `none`
+impl FromStr for LockPolicy {
+    type Err = UnknownKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "all" => return Ok(LockPolicy::ALL),
+            "none" | "" => return Ok(LockPolicy::NONE),
+            _ => {}
+        }
+
+        let mut policy = LockPolicy::NONE;
+        for kind in s.split(',').map(str::trim) {
+            *policy
+                .kind_mut(kind)
+                .ok_or_else(|| UnknownKind(kind.to_string()))? = true;
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for LockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blocked = [
+            self.deposits,
+            self.withdrawals,
+            self.disputes,
+            self.resolves,
+        ];
+        let kinds = LockPolicy::KINDS
+            .iter()
+            .zip(blocked)
+            .filter(|(_, blocked)| *blocked)
+            .map(|(kind, _)| *kind)
+            .collect::<Vec<_>>();
+
+        if kinds.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", kinds.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_blocked_kinds() {
+        assert_eq!("withdrawals".parse(), Ok(LockPolicy::default()));
+        assert_eq!("none".parse(), Ok(LockPolicy::NONE));
+        assert_eq!("ALL".parse(), Ok(LockPolicy::ALL));
+        assert_eq!(
+            " Deposits, dispute ".parse(),
+            Ok(LockPolicy {
+                deposits: true,
+                disputes: true,
+                ..LockPolicy::NONE
+            })
+        );
+        assert_eq!(
+            "deposits,chargebacks".parse::<LockPolicy>(),
+            Err(UnknownKind("chargebacks".to_string()))
+        );
+
+        for policy in [LockPolicy::default(), LockPolicy::NONE, LockPolicy::ALL] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+    }
+}
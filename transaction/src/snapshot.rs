@@ -0,0 +1,230 @@
+use crate::{
+    ClientAccount, DisputeState, FraudAction, FraudDecision, LedgerEntry, Money, StorageError,
+    Transaction,
+};
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Bumped whenever the layout below changes incompatibly
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Point-in-time copy of an engine's accounts and ledger.
+///
+/// `sequence` is the last journal entry the snapshot includes, so a
+/// restore only has to replay the journal entries after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub sequence: u64,
+    pub accounts: Vec<ClientAccount>,
+    pub ledger: Vec<(u64, LedgerEntry)>,
+}
+
+// On-disk layout, kept apart from the public types so
+// their output formats can change without breaking old files
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    version: u32,
+    sequence: u64,
+    accounts: Vec<AccountRecord>,
+    ledger: Vec<EntryRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountRecord {
+    client: u16,
+    available: Money,
+    held: Money,
+    locked: bool,
+    // Missing from snapshots taken before accounts could be closed
+    #[serde(default)]
+    closed: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fraud_decisions: Vec<DecisionRecord>,
+}
+
+// The client is the account's
+#[derive(Serialize, Deserialize)]
+struct DecisionRecord {
+    rule: String,
+    action: FraudAction,
+    tx: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntryRecord {
+    tx: u64,
+    transaction: Transaction,
+    state: DisputeState,
+}
+
+fn snapshot_err(path: &Path, err: impl std::fmt::Display) -> StorageError {
+    StorageError::new(format!("snapshot {}: {}", path.display(), err))
+}
+
+impl Snapshot {
+    /// Writes the snapshot to `path`, replacing any previous one.
+    ///
+    /// The file is written next to `path` and renamed over it once
+    /// synced, so a crash never leaves a half-written snapshot behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        let path = path.as_ref();
+        let file = SnapshotFile {
+            version: SNAPSHOT_VERSION,
+            sequence: self.sequence,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|account| AccountRecord {
+                    client: account.client_id,
+                    available: account.available,
+                    held: account.held,
+                    locked: account.locked,
+                    closed: account.closed,
+                    fraud_decisions: account
+                        .fraud_decisions
+                        .iter()
+                        .map(|decision| DecisionRecord {
+                            rule: decision.rule.clone(),
+                            action: decision.action,
+                            tx: decision.transaction_id,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            ledger: self
+                .ledger
+                .iter()
+                .map(|(tx, entry)| EntryRecord {
+                    tx: *tx,
+                    transaction: entry.transaction.clone(),
+                    state: entry.state,
+                })
+                .collect(),
+        };
+
+        let mut temp_path = PathBuf::from(path);
+        temp_path.as_mut_os_string().push(".tmp");
+
+        let temp = File::create(&temp_path).map_err(|err| snapshot_err(path, err))?;
+        let mut writer = BufWriter::new(temp);
+        serde_json::to_writer(&mut writer, &file).map_err(|err| snapshot_err(path, err))?;
+        writer.flush().map_err(|err| snapshot_err(path, err))?;
+        writer
+            .get_ref()
+            .sync_all()
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|err| snapshot_err(path, err))
+    }
+
+    /// Reads the snapshot at `path`, None if there isn't one yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, StorageError> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(snapshot_err(path, err)),
+        };
+
+        let file: SnapshotFile =
+            serde_json::from_reader(BufReader::new(file)).map_err(|err| snapshot_err(path, err))?;
+        if file.version != SNAPSHOT_VERSION {
+            return Err(snapshot_err(
+                path,
+                format!("unsupported version {}", file.version),
+            ));
+        }
+
+        Ok(Some(Snapshot {
+            sequence: file.sequence,
+            accounts: file
+                .accounts
+                .into_iter()
+                .map(|record| ClientAccount {
+                    client_id: record.client,
+                    available: record.available,
+                    held: record.held,
+                    locked: record.locked,
+                    closed: record.closed,
+                    fraud_decisions: record
+                        .fraud_decisions
+                        .into_iter()
+                        .map(|decision| FraudDecision {
+                            rule: decision.rule,
+                            action: decision.action,
+                            client_id: record.client,
+                            transaction_id: decision.tx,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            ledger: file
+                .ledger
+                .into_iter()
+                .map(|record| {
+                    let entry = LedgerEntry {
+                        transaction: record.transaction,
+                        state: record.state,
+                    };
+                    (record.tx, entry)
+                })
+                .collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_loads_snapshot() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot.json");
+        assert_eq!(Snapshot::load(&path)?, None);
+
+        let mut account = ClientAccount::new(4);
+        account.available = "1.2345".parse()?;
+        account.held = "10".parse()?;
+        account.locked = true;
+        account.fraud_decisions.push(FraudDecision {
+            rule: "dispute_velocity".to_string(),
+            action: FraudAction::Lock,
+            client_id: 4,
+            transaction_id: 9,
+        });
+
+        let entry = LedgerEntry {
+            transaction: Transaction::Deposit {
+                transaction_id: 9,
+                client_id: 4,
+                amount: "10".parse()?,
+            },
+            state: DisputeState::ChargedBack,
+        };
+
+        let snapshot = Snapshot {
+            sequence: 12,
+            accounts: vec![account],
+            ledger: vec![(9, entry)],
+        };
+        snapshot.save(&path)?;
+        assert_eq!(Snapshot::load(&path)?, Some(snapshot));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_version() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot.json");
+        fs::write(
+            &path,
+            r#"{"version":99,"sequence":0,"accounts":[],"ledger":[]}"#,
+        )?;
+
+        assert!(Snapshot::load(&path).is_err());
+        Ok(())
+    }
+}
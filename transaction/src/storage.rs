@@ -0,0 +1,101 @@
+use crate::{AccountStore, ClientAccount, InputPosition, Ledger, LedgerEntry};
+use std::fmt;
+
+/// Failure reported by a storage backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageError(String);
+
+impl StorageError {
+    pub fn new(message: impl fmt::Display) -> Self {
+        StorageError(message.to_string())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "storage error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Where a `TransactionEngine` keeps its client accounts.
+///
+/// Accounts are loaded and saved by value, so implementations are free
+/// to keep them anywhere. Writes only need to become durable on `commit`.
+pub trait AccountRepository {
+    fn find(&self, client_id: u16) -> Result<Option<ClientAccount>, StorageError>;
+
+    fn save(&mut self, account: ClientAccount) -> Result<(), StorageError>;
+
+    // Every client account, in no particular order
+    fn all(&self) -> Result<Vec<ClientAccount>, StorageError>;
+
+    /// Last input record applied as of the last commit, which is
+    /// where a resumed run should pick up after
+    fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        Ok(InputPosition::default())
+    }
+
+    /// Makes every write so far durable, recording that the input has
+    /// been applied up to and including `checkpoint`
+    fn commit(&mut self, _checkpoint: &InputPosition) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Where a `TransactionEngine` keeps accepted deposits, withdrawals and
+/// administrative operations.
+///
+/// Same loading and durability rules as `AccountRepository`.
+pub trait LedgerRepository {
+    fn find(&self, transaction_id: u64) -> Result<Option<LedgerEntry>, StorageError>;
+
+    fn save(&mut self, transaction_id: u64, entry: LedgerEntry) -> Result<(), StorageError>;
+
+    // Every entry with its transaction id, in no particular order
+    fn all(&self) -> Result<Vec<(u64, LedgerEntry)>, StorageError>;
+
+    fn checkpoint(&self) -> Result<InputPosition, StorageError> {
+        Ok(InputPosition::default())
+    }
+
+    fn commit(&mut self, _checkpoint: &InputPosition) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+// The in-memory defaults, nothing survives the process
+
+impl AccountRepository for AccountStore {
+    fn find(&self, client_id: u16) -> Result<Option<ClientAccount>, StorageError> {
+        Ok(self.get(&client_id).cloned())
+    }
+
+    fn save(&mut self, account: ClientAccount) -> Result<(), StorageError> {
+        self.insert(account.client_id, account);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<ClientAccount>, StorageError> {
+        Ok(self.values().cloned().collect())
+    }
+}
+
+impl LedgerRepository for Ledger {
+    fn find(&self, transaction_id: u64) -> Result<Option<LedgerEntry>, StorageError> {
+        Ok(self.get(&transaction_id).cloned())
+    }
+
+    fn save(&mut self, transaction_id: u64, entry: LedgerEntry) -> Result<(), StorageError> {
+        self.insert(transaction_id, entry);
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<(u64, LedgerEntry)>, StorageError> {
+        Ok(self
+            .iter()
+            .map(|(id, entry)| (*id, entry.clone()))
+            .collect())
+    }
+}
@@ -0,0 +1,311 @@
+use crate::input::{parse_csv_line, parse_csv_record};
+use crate::{
+    AccountRepository, AccountStore, EngineError, Ledger, LedgerRepository, StorageError,
+    Transaction, TransactionEngine,
+};
+
+use csv::StringRecord;
+use futures::stream::{self, Stream, StreamExt};
+use std::fmt;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+use tokio::sync::Mutex;
+
+/// An engine shared by any number of concurrent streams
+pub type SharedEngine<A = AccountStore, L = Ledger> = Arc<Mutex<TransactionEngine<A, L>>>;
+
+/// What happened to one record of a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted(Transaction),
+    Rejected(Transaction, EngineError),
+
+    // Only for CSV input, the line couldn't be parsed into a transaction
+    Malformed { line: u64, message: String },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Accepted(transaction) => {
+                write!(
+                    f,
+                    "accepted {} {}",
+                    transaction,
+                    transaction.transaction_id()
+                )
+            }
+            Outcome::Rejected(transaction, err) => write!(
+                f,
+                "rejected {} {}: {}",
+                transaction,
+                transaction.transaction_id(),
+                err
+            ),
+            Outcome::Malformed { line, message } => {
+                write!(f, "malformed line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+/// Hands each transaction to the shared engine as it arrives and yields its outcome.
+///
+/// The engine is only locked for one transaction at a time, so any
+/// number of streams can be fed concurrently and interleave fairly.
+/// Each stream's transactions are still applied in order, see
+/// `handle_shared` for which of them block.
+pub fn handle_stream<S, A, L>(
+    engine: SharedEngine<A, L>,
+    transactions: S,
+) -> impl Stream<Item = Outcome>
+where
+    S: Stream<Item = Transaction>,
+    A: AccountRepository + Send + 'static,
+    L: LedgerRepository + Send + 'static,
+{
+    transactions.then(move |transaction| {
+        let engine = engine.clone();
+        async move { handle_one(&engine, transaction).await }
+    })
+}
+
+/// Same as `handle_stream`, for CSV text.
+///
+/// A leading header line is optional, without one the columns are
+/// `type, client, tx, amount`. Records are read a line at a time,
+/// so unlike files they can't contain quoted line breaks.
+pub fn handle_csv<R, A, L>(engine: SharedEngine<A, L>, reader: R) -> impl Stream<Item = Outcome>
+where
+    R: AsyncRead + Unpin,
+    A: AccountRepository + Send + 'static,
+    L: LedgerRepository + Send + 'static,
+{
+    read_csv(reader).then(move |parsed| {
+        let engine = engine.clone();
+        async move {
+            match parsed {
+                Ok(transaction) => handle_one(&engine, transaction).await,
+                Err(malformed) => malformed,
+            }
+        }
+    })
+}
+
+async fn handle_one<A, L>(engine: &SharedEngine<A, L>, transaction: Transaction) -> Outcome
+where
+    A: AccountRepository + Send + 'static,
+    L: LedgerRepository + Send + 'static,
+{
+    match handle_shared(engine, transaction.clone()).await {
+        Ok(()) => Outcome::Accepted(transaction),
+        Err(err) => Outcome::Rejected(transaction, err),
+    }
+}
+
+/// Hands one transaction to the shared engine, as `handle_stream` does.
+///
+/// Works under any executor. Within a tokio runtime an engine with a
+/// journal syncs it on a blocking thread, so the runtime's workers
+/// aren't held up by the disk. Elsewhere, and for other repositories
+/// that do I/O like `DiskStorage`, the calling task blocks.
+pub async fn handle_shared<A, L>(
+    engine: &SharedEngine<A, L>,
+    transaction: Transaction,
+) -> Result<(), EngineError>
+where
+    A: AccountRepository + Send + 'static,
+    L: LedgerRepository + Send + 'static,
+{
+    let mut engine = engine.clone().lock_owned().await;
+    let Some(runtime) = engine
+        .journal()
+        .and_then(|_| tokio::runtime::Handle::try_current().ok())
+    else {
+        return engine.handle(transaction);
+    };
+
+    runtime
+        .spawn_blocking(move || engine.handle(transaction))
+        .await
+        .unwrap_or_else(|err| {
+            Err(StorageError::new(format!("journal write did not finish: {}", err)).into())
+        })
+}
+
+struct CsvLines<R> {
+    lines: Lines<BufReader<R>>,
+    headers: Option<StringRecord>,
+    line: u64,
+    done: bool,
+}
+
+// Parsed transactions, or the `Outcome::Malformed` of lines that aren't
+fn read_csv<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = Result<Transaction, Outcome>> {
+    let state = CsvLines {
+        lines: BufReader::new(reader).lines(),
+        headers: None,
+        line: 0,
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        while !state.done {
+            state.line += 1;
+            let malformed = |message: String| Outcome::Malformed {
+                line: state.line,
+                message,
+            };
+
+            let text = match state.lines.next_line().await {
+                Ok(Some(text)) => text,
+                Ok(None) => return None,
+                Err(err) => {
+                    // Nothing more can be read after an I/O error
+                    state.done = true;
+                    return Some((Err(malformed(err.to_string())), state));
+                }
+            };
+
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let record = match parse_csv_line(&text) {
+                Ok(record) => record,
+                Err(err) => return Some((Err(malformed(err.to_string())), state)),
+            };
+
+            if state.headers.is_none() && is_header(&record) {
+                state.headers = Some(record);
+                continue;
+            }
+            let headers = state
+                .headers
+                .get_or_insert_with(|| StringRecord::from(vec!["type", "client", "tx", "amount"]));
+
+            let parsed =
+                parse_csv_record(&record, headers).map_err(|err| malformed(err.to_string()));
+            return Some((parsed, state));
+        }
+        None
+    })
+}
+
+// No transaction type is called "type"
+fn is_header(record: &StringRecord) -> bool {
+    record
+        .get(0)
+        .is_some_and(|field| field.eq_ignore_ascii_case("type"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Money;
+
+    #[tokio::test]
+    async fn shares_engine_between_streams() -> anyhow::Result<()> {
+        let engine: SharedEngine = Arc::new(Mutex::new(TransactionEngine::default()));
+
+        let csv =
+            "type, client, tx, amount\n\ndeposit, 1, 1, 2.0\nnonsense, 1\nwithdrawal, 1, 2, 5.0\n";
+        let deposit = Transaction::Deposit {
+            transaction_id: 3,
+            client_id: 2,
+            amount: "1.5".parse()?,
+        };
+        let dispute = Transaction::Dispute {
+            transaction_id: 1,
+            client_id: 1,
+        };
+
+        let (from_csv, from_stream) = futures::future::join(
+            handle_csv(engine.clone(), csv.as_bytes()).collect::<Vec<_>>(),
+            handle_stream(engine.clone(), stream::iter([deposit.clone()])).collect::<Vec<_>>(),
+        )
+        .await;
+
+        assert_eq!(from_csv.len(), 3);
+        assert!(matches!(from_csv[0], Outcome::Accepted(_)));
+        assert!(matches!(from_csv[1], Outcome::Malformed { line: 4, .. }));
+        assert!(matches!(
+            from_csv[2],
+            Outcome::Rejected(_, EngineError::InsufficientFunds { .. })
+        ));
+        assert_eq!(from_stream, vec![Outcome::Accepted(deposit)]);
+
+        // Both streams landed in the same engine
+        let outcomes = handle_stream(engine.clone(), stream::iter([dispute.clone()]))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(outcomes, vec![Outcome::Accepted(dispute)]);
+
+        let engine = engine.lock().await;
+        assert_eq!(engine.client_accounts[&1].held, "2.0".parse::<Money>()?);
+        assert_eq!(
+            engine.client_accounts[&2].available,
+            "1.5".parse::<Money>()?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_csv_without_header() -> anyhow::Result<()> {
+        let engine: SharedEngine = Arc::new(Mutex::new(TransactionEngine::default()));
+
+        let outcomes = handle_csv(
+            engine.clone(),
+            "deposit, 1, 1, 2.0\ndispute, 1, 1,\n".as_bytes(),
+        )
+        .map(|outcome| outcome.to_string())
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(outcomes, vec!["accepted Deposit 1", "accepted Dispute 1"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn journals_off_the_runtime() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+        let mut engine = TransactionEngine::default();
+        engine.recover(&path)?;
+        let engine: SharedEngine = Arc::new(Mutex::new(engine));
+
+        let outcomes = handle_csv(
+            engine.clone(),
+            "deposit, 1, 1, 2.0
+"
+            .as_bytes(),
+        )
+        .map(|outcome| outcome.to_string())
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(outcomes, vec!["accepted Deposit 1"]);
+
+        let mut recovered = TransactionEngine::default();
+        assert_eq!(recovered.recover(&path)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn journals_without_tokio() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal.jsonl");
+        let mut engine = TransactionEngine::default();
+        engine.recover(&path)?;
+        let engine: SharedEngine = Arc::new(Mutex::new(engine));
+
+        let deposit = Transaction::Deposit {
+            transaction_id: 1,
+            client_id: 1,
+            amount: "2.0".parse()?,
+        };
+        futures::executor::block_on(handle_shared(&engine, deposit))?;
+
+        let mut recovered = TransactionEngine::default();
+        assert_eq!(recovered.recover(&path)?, 1);
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
-use serde::Deserialize;
+use crate::Money;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::cmp::PartialEq;
 use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+// Deserialized by hand below, so the type can be any case
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Transaction {
     Deposit {
@@ -10,20 +13,14 @@ pub enum Transaction {
         transaction_id: u64,
         #[serde(rename = "client")]
         client_id: u16,
-        amount: f64,
-
-        // For internal use to track whether
-        // this transaction has been disputed
-        #[serde(skip_deserializing, default)]
-        disputed: bool,
+        amount: Money,
     },
-    #[serde(alias = "withdrawal")]
     Withdraw {
         #[serde(rename = "tx")]
         transaction_id: u64,
         #[serde(rename = "client")]
         client_id: u16,
-        amount: f64,
+        amount: Money,
     },
     Dispute {
         #[serde(rename = "tx")]
@@ -43,6 +40,108 @@ pub enum Transaction {
         #[serde(rename = "client")]
         client_id: u16,
     },
+
+    // Administrative operations from support staff rather than the client.
+    // Their id is their own, like a deposit's, and they're recorded in the
+    // ledger with who did them and why.
+    Freeze {
+        #[serde(rename = "tx")]
+        transaction_id: u64,
+        #[serde(rename = "client")]
+        client_id: u16,
+        operator: String,
+        reason: String,
+    },
+    Unfreeze {
+        #[serde(rename = "tx")]
+        transaction_id: u64,
+        #[serde(rename = "client")]
+        client_id: u16,
+        operator: String,
+        reason: String,
+    },
+    Close {
+        #[serde(rename = "tx")]
+        transaction_id: u64,
+        #[serde(rename = "client")]
+        client_id: u16,
+        operator: String,
+        reason: String,
+    },
+}
+
+impl Transaction {
+    /// Names of the transaction types, as written in the `type` column
+    pub const TYPES: &[&str] = &[
+        "deposit",
+        "withdraw",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "freeze",
+        "unfreeze",
+        "close",
+    ];
+
+    /// The type name `name` stands for, ignoring case
+    pub fn type_name(name: &str) -> Option<&'static str> {
+        Self::TYPES
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    pub fn client_id(&self) -> u16 {
+        match *self {
+            Transaction::Deposit { client_id, .. }
+            | Transaction::Withdraw { client_id, .. }
+            | Transaction::Dispute { client_id, .. }
+            | Transaction::Resolve { client_id, .. }
+            | Transaction::Chargeback { client_id, .. }
+            | Transaction::Freeze { client_id, .. }
+            | Transaction::Unfreeze { client_id, .. }
+            | Transaction::Close { client_id, .. } => client_id,
+        }
+    }
+
+    // For disputes, resolves and chargebacks this is the referenced transaction
+    pub fn transaction_id(&self) -> u64 {
+        match *self {
+            Transaction::Deposit { transaction_id, .. }
+            | Transaction::Withdraw { transaction_id, .. }
+            | Transaction::Dispute { transaction_id, .. }
+            | Transaction::Resolve { transaction_id, .. }
+            | Transaction::Chargeback { transaction_id, .. }
+            | Transaction::Freeze { transaction_id, .. }
+            | Transaction::Unfreeze { transaction_id, .. }
+            | Transaction::Close { transaction_id, .. } => transaction_id,
+        }
+    }
+
+    /// Whether this is a freeze, unfreeze or close from support staff
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Transaction::Freeze { .. } | Transaction::Unfreeze { .. } | Transaction::Close { .. }
+        )
+    }
+
+    /// Who ran an administrative operation and why
+    pub fn authorization(&self) -> Option<(&str, &str)> {
+        match self {
+            Transaction::Freeze {
+                operator, reason, ..
+            }
+            | Transaction::Unfreeze {
+                operator, reason, ..
+            }
+            | Transaction::Close {
+                operator, reason, ..
+            } => Some((operator, reason)),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Transaction {
@@ -54,6 +153,134 @@ impl fmt::Display for Transaction {
             Transaction::Dispute { .. } => write!(f, "Dispute"),
             Transaction::Resolve { .. } => write!(f, "Resolve"),
             Transaction::Chargeback { .. } => write!(f, "Chargeback"),
+            Transaction::Freeze { .. } => write!(f, "Freeze"),
+            Transaction::Unfreeze { .. } => write!(f, "Unfreeze"),
+            Transaction::Close { .. } => write!(f, "Close"),
+        }
+    }
+}
+
+// The record as written, before the type is known
+#[derive(Deserialize)]
+struct TransactionRecord {
+    #[serde(rename = "type")]
+    kind: String,
+    client: u16,
+    tx: u64,
+    // Disputes, resolves and chargebacks may leave it out
+    #[serde(default)]
+    amount: Amount,
+    // Only read for the administrative types
+    #[serde(default)]
+    operator: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// Only parsed into money for the types that have an amount,
+// the others ignore whatever was written in the column.
+//
+// Always read from the text as written. Left to itself the CSV reader
+// would infer a float, rounding away digits before `Money` sees them.
+#[derive(Default)]
+struct Amount(Option<Result<Money, String>>);
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        if v.trim().is_empty() {
+            return Ok(Amount(None));
+        }
+        Ok(Amount(Some(v.parse().map_err(|err| format!("{}", err)))))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Amount, E> {
+        Ok(Amount(None))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Amount, E> {
+        Ok(Amount(None))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Amount, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_option(AmountVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Transaction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let record = TransactionRecord::deserialize(deserializer)?;
+        let amount = move || match record.amount.0 {
+            Some(amount) => amount.map_err(de::Error::custom),
+            None => Err(de::Error::missing_field("amount")),
+        };
+
+        let operator = record.operator.ok_or(de::Error::missing_field("operator"));
+        let reason = record.reason.ok_or(de::Error::missing_field("reason"));
+
+        let transaction_id = record.tx;
+        let client_id = record.client;
+        match Transaction::type_name(&record.kind) {
+            Some("deposit") => Ok(Transaction::Deposit {
+                transaction_id,
+                client_id,
+                amount: amount()?,
+            }),
+            Some("withdraw" | "withdrawal") => Ok(Transaction::Withdraw {
+                transaction_id,
+                client_id,
+                amount: amount()?,
+            }),
+            Some("dispute") => Ok(Transaction::Dispute {
+                transaction_id,
+                client_id,
+            }),
+            Some("resolve") => Ok(Transaction::Resolve {
+                transaction_id,
+                client_id,
+            }),
+            Some("chargeback") => Ok(Transaction::Chargeback {
+                transaction_id,
+                client_id,
+            }),
+            Some("freeze") => Ok(Transaction::Freeze {
+                transaction_id,
+                client_id,
+                operator: operator?,
+                reason: reason?,
+            }),
+            Some("unfreeze") => Ok(Transaction::Unfreeze {
+                transaction_id,
+                client_id,
+                operator: operator?,
+                reason: reason?,
+            }),
+            Some("close") => Ok(Transaction::Close {
+                transaction_id,
+                client_id,
+                operator: operator?,
+                reason: reason?,
+            }),
+            _ => Err(de::Error::unknown_variant(&record.kind, Self::TYPES)),
         }
     }
 }
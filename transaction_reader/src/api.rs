@@ -0,0 +1,182 @@
+use transaction::input::transaction_from_json;
+use transaction::stream::handle_shared;
+use transaction::{
+    AccountRepository, ClientAccount, EngineError, LedgerEntry, LedgerRepository, SharedEngine,
+    StorageError, Transaction,
+};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+
+// Routes of the HTTP/JSON service, every response body is JSON
+pub fn router(engine: SharedEngine) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transaction))
+        .route("/transactions/{tx}", get(get_transaction))
+        .route("/accounts", get(get_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .with_state(engine)
+}
+
+#[derive(Debug)]
+enum ApiError {
+    NotFound(String),
+    Malformed(serde_json::Error),
+    Rejected(EngineError),
+}
+
+impl From<StorageError> for ApiError {
+    fn from(err: StorageError) -> Self {
+        ApiError::Rejected(err.into())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error) = match self {
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Malformed(err) => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+            ApiError::Rejected(err @ EngineError::Storage(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            ApiError::Rejected(err) => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+        };
+        (status, Json(ErrorBody { error })).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct Accepted {
+    status: &'static str,
+    transaction: Transaction,
+}
+
+// Takes the same fields as a CSV record, e.g.
+// {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+async fn post_transaction(
+    State(engine): State<SharedEngine>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<Accepted>, ApiError> {
+    let transaction = transaction_from_json(body).map_err(ApiError::Malformed)?;
+    handle_shared(&engine, transaction.clone())
+        .await
+        .map_err(ApiError::Rejected)?;
+
+    Ok(Json(Accepted {
+        status: "accepted",
+        transaction,
+    }))
+}
+
+// Only deposits, withdrawals and administrative operations are kept,
+// see `TransactionEngine::handle`
+async fn get_transaction(
+    State(engine): State<SharedEngine>,
+    Path(transaction_id): Path<u64>,
+) -> Result<Json<LedgerEntry>, ApiError> {
+    let entry = engine.lock().await.ledger.find(transaction_id)?;
+    entry
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("transaction {} does not exist", transaction_id)))
+}
+
+async fn get_accounts(
+    State(engine): State<SharedEngine>,
+) -> Result<Json<Vec<ClientAccount>>, ApiError> {
+    let mut accounts = engine.lock().await.client_accounts.all()?;
+    accounts.sort_by_key(|account| account.client_id);
+    Ok(Json(accounts))
+}
+
+async fn get_account(
+    State(engine): State<SharedEngine>,
+    Path(client_id): Path<u16>,
+) -> Result<Json<ClientAccount>, ApiError> {
+    let account = engine.lock().await.client_accounts.find(client_id)?;
+    account
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("client {} does not exist", client_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction::TransactionEngine;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    async fn call(
+        router: &Router,
+        method: &str,
+        uri: &str,
+        body: &str,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))?;
+
+        let response = router.clone().oneshot(request).await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, serde_json::from_slice(&body)?))
+    }
+
+    #[tokio::test]
+    async fn serves_transactions_and_accounts() -> anyhow::Result<()> {
+        let engine: SharedEngine = Arc::new(Mutex::new(TransactionEngine::default()));
+        let router = router(engine);
+
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5}"#;
+        let (status, _) = call(&router, "POST", "/transactions", deposit).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "9"}"#;
+        let (status, body) = call(&router, "POST", "/transactions", withdrawal).await?;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["error"],
+            "client account 1 has 2.5000 available but needs 9.0000"
+        );
+
+        let dispute = r#"{"type": "dispute", "client": 1, "tx": 1}"#;
+        let (status, _) = call(&router, "POST", "/transactions", dispute).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = call(&router, "GET", "/accounts/1", "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["held"], "2.5000");
+        assert_eq!(body["available"], "0.0000");
+        assert_eq!(body["fraud_flags"], 0);
+        assert_eq!(body["fraud_decisions"], serde_json::json!([]));
+
+        let (status, body) = call(&router, "GET", "/accounts", "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().map(Vec::len), Some(1));
+
+        let (status, body) = call(&router, "GET", "/transactions/1", "").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["state"], "disputed");
+        assert_eq!(body["transaction"]["amount"], "2.5000");
+
+        let (status, _) = call(&router, "GET", "/transactions/2", "").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&router, "GET", "/accounts/2", "").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
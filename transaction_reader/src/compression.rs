@@ -0,0 +1,95 @@
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Cursor, Read};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        if name.ends_with(".gz") {
+            Some(Compression::Gzip)
+        } else if name.ends_with(".zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+// Decompresses `reader` on the fly if it's gzip or zstd, going by its
+// first bytes and then by the extension of `name`. Anything else is
+// passed through as it is.
+pub fn decompress<'r>(name: &str, mut reader: impl Read + 'r) -> io::Result<Box<dyn Read + 'r>> {
+    // Read rather than peeked, a pipe may hand them over a byte at a time
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    reader
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+
+    let compression = Compression::from_magic(&magic).or_else(|| Compression::from_name(name));
+    let reader = Cursor::new(magic).chain(reader);
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(reader)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+        None => Box::new(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const CSV: &str = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+
+    fn read_all(name: &str, bytes: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        decompress(name, bytes)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn decompresses_by_magic_bytes() -> anyhow::Result<()> {
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(CSV.as_bytes())?;
+        let gzip = gzip.finish()?;
+        let zstd = zstd::encode_all(CSV.as_bytes(), 0)?;
+
+        // Whatever the files are called
+        assert_eq!(read_all("day.csv", &gzip)?, CSV);
+        assert_eq!(read_all("-", &zstd)?, CSV);
+        assert_eq!(read_all("day.csv", CSV.as_bytes())?, CSV);
+        assert_eq!(read_all("day.csv", b"ty")?, "ty");
+
+        // Concatenated gzip members read as one file
+        let twice = [gzip.as_slice(), gzip.as_slice()].concat();
+        assert_eq!(read_all("day.csv.gz", &twice)?, CSV.repeat(2));
+        Ok(())
+    }
+
+    #[test]
+    fn fails_on_corrupt_archives() {
+        // Without the magic only the name says it's compressed, which is
+        // better reported than parsed as CSV
+        assert!(read_all("day.csv.zst", CSV.as_bytes()).is_err());
+        assert!(read_all("day.csv.gz", b"t").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use transaction::fraud::{
+    DepositThenWithdrawal, DepositWithdrawDispute, DisputeVelocity, WithdrawalVelocity,
+};
+use transaction::{
+    AccountRepository, FraudAction, LedgerRepository, LockPolicy, Money, TransactionEngine,
+};
+
+use clap::Args;
+
+// How the engines of both file runs and servers behave
+#[derive(Debug, Default, Clone, Args)]
+pub struct EngineArgs {
+    /// Transactions refused on locked accounts, a comma separated list of
+    /// deposits, withdrawals, disputes and resolves, or all or none.
+    #[arg(long, value_name = "KINDS", default_value_t = LockPolicy::default())]
+    pub lock_policy: LockPolicy,
+
+    /// Run this fraud rule on every transaction, alerting on stderr. One of
+    /// `deposit-withdraw-dispute`, `deposit-then-withdrawal`,
+    /// `dispute-velocity=N/M` (more than N disputes in a client's last M
+    /// transactions) or `withdrawal-velocity=AMOUNT/M` (withdrawals over
+    /// AMOUNT in them), optionally followed by `:flag`, `:lock` (the default)
+    /// or `:reject`. Can be given more than once.
+    #[arg(long = "fraud-rule", value_name = "RULE", value_parser = parse_fraud_rule)]
+    pub fraud_rules: Vec<FraudRuleArg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FraudRuleArg {
+    DepositWithdrawDispute(FraudAction),
+    DepositThenWithdrawal(FraudAction),
+    DisputeVelocity {
+        max: usize,
+        window: usize,
+        action: FraudAction,
+    },
+    WithdrawalVelocity {
+        limit: Money,
+        window: usize,
+        action: FraudAction,
+    },
+}
+
+impl EngineArgs {
+    pub fn configure<A: AccountRepository, L: LedgerRepository>(
+        &self,
+        engine: TransactionEngine<A, L>,
+    ) -> TransactionEngine<A, L> {
+        let mut engine = engine.with_lock_policy(self.lock_policy);
+        for rule in &self.fraud_rules {
+            engine = match *rule {
+                FraudRuleArg::DepositWithdrawDispute(action) => {
+                    engine.with_fraud_rule(DepositWithdrawDispute { action })
+                }
+                FraudRuleArg::DepositThenWithdrawal(action) => {
+                    engine.with_fraud_rule(DepositThenWithdrawal::new(action))
+                }
+                FraudRuleArg::DisputeVelocity {
+                    max,
+                    window,
+                    action,
+                } => engine.with_fraud_rule(DisputeVelocity::new(max, window, action)),
+                FraudRuleArg::WithdrawalVelocity {
+                    limit,
+                    window,
+                    action,
+                } => engine.with_fraud_rule(WithdrawalVelocity::new(limit, window, action)),
+            };
+        }
+        engine.on_fraud_alert(|decision| eprintln!("fraud alert: {}", decision))
+    }
+}
+
+// Reads `--fraud-rule NAME[=LIMIT/WINDOW][:ACTION]`
+pub fn parse_fraud_rule(value: &str) -> Result<FraudRuleArg, String> {
+    let (rule, action) = match value.rsplit_once(':') {
+        Some((rule, action)) => (rule, parse_action(action)?),
+        None => (value, FraudAction::Lock),
+    };
+    let (name, written) = match rule.split_once('=') {
+        Some((name, written)) => (name.trim(), Some(written)),
+        None => (rule.trim(), None),
+    };
+
+    // Both velocity rules take a limit and a window, the others nothing
+    let limits = |expected: &str| {
+        let (limit, window) = written
+            .and_then(|written| written.split_once('/'))
+            .ok_or_else(|| format!("expected {}={}, got `{}`", name, expected, value))?;
+        let window = window
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|window| *window > 0)
+            .ok_or_else(|| format!("window `{}` isn't a positive number", window.trim()))?;
+        Ok::<_, String>((limit.trim(), window))
+    };
+    let no_limits = |rule| match written {
+        Some(_) => Err(format!("{} takes no limits, got `{}`", name, value)),
+        None => Ok(rule),
+    };
+
+    match name {
+        "deposit-withdraw-dispute" => no_limits(FraudRuleArg::DepositWithdrawDispute(action)),
+        "deposit-then-withdrawal" => no_limits(FraudRuleArg::DepositThenWithdrawal(action)),
+        "dispute-velocity" => {
+            let (max, window) = limits("N/M")?;
+            let max = max
+                .parse()
+                .map_err(|_| format!("dispute count `{}` isn't a number", max))?;
+            Ok(FraudRuleArg::DisputeVelocity {
+                max,
+                window,
+                action,
+            })
+        }
+        "withdrawal-velocity" => {
+            let (limit, window) = limits("AMOUNT/M")?;
+            let limit = limit.parse().map_err(|err| format!("{}", err))?;
+            Ok(FraudRuleArg::WithdrawalVelocity {
+                limit,
+                window,
+                action,
+            })
+        }
+        _ => Err(format!("unknown fraud rule `{}`", name)),
+    }
+}
+
+fn parse_action(action: &str) -> Result<FraudAction, String> {
+    match action.trim().to_ascii_lowercase().as_str() {
+        "flag" => Ok(FraudAction::Flag),
+        "lock" => Ok(FraudAction::Lock),
+        "reject" => Ok(FraudAction::Reject),
+        _ => Err(format!(
+            "unknown fraud action `{}`, expected flag, lock or reject",
+            action.trim()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fraud_rules() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_fraud_rule("deposit-withdraw-dispute"),
+            Ok(FraudRuleArg::DepositWithdrawDispute(FraudAction::Lock))
+        );
+        assert_eq!(
+            parse_fraud_rule("deposit-then-withdrawal:Flag"),
+            Ok(FraudRuleArg::DepositThenWithdrawal(FraudAction::Flag))
+        );
+        assert_eq!(
+            parse_fraud_rule("dispute-velocity=3/10"),
+            Ok(FraudRuleArg::DisputeVelocity {
+                max: 3,
+                window: 10,
+                action: FraudAction::Lock,
+            })
+        );
+        assert_eq!(
+            parse_fraud_rule("withdrawal-velocity = 500.5/20 : reject"),
+            Ok(FraudRuleArg::WithdrawalVelocity {
+                limit: "500.5".parse()?,
+                window: 20,
+                action: FraudAction::Reject,
+            })
+        );
+
+        for invalid in [
+            "chargeback-velocity=1/2",
+            "dispute-velocity",
+            "dispute-velocity=3",
+            "dispute-velocity=3/0",
+            "withdrawal-velocity=lots/5",
+            "deposit-withdraw-dispute=1/2",
+            "deposit-withdraw-dispute:ban",
+        ] {
+            assert!(parse_fraud_rule(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+}
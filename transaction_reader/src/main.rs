@@ -1,78 +1,614 @@
-use transaction::{Transaction, TransactionEngine};
-
-use clap::Parser;
-use csv::{ReaderBuilder, WriterBuilder};
-use serde::Deserialize;
-
-use std::io::Read;
-use std::{fs::File, path::Path};
+mod api;
+mod compression;
+mod engine;
+mod output;
+mod rejects;
+mod serve;
+
+use transaction::{
+    AccountRepository, DiskStorage, InputPosition, InputRecord, LedgerRepository, Reject,
+    SharedEngine, Transaction, TransactionEngine, Validation,
+    input::{self, Column, FIELDS, ReadOptions, UnknownField, UnknownType},
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use csv::StringRecord;
+use engine::EngineArgs;
+use output::OutputArgs;
+use rejects::RejectLog;
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+// Input name that stands for stdin
+const STDIN: &str = "-";
+
+// Records applied between each storage commit
+const COMMIT_INTERVAL: u64 = 10_000;
+
+// Transactions handed to a worker at a time, sending them
+// one by one spends more time in the channel than the engine
+const SHARD_BATCH: usize = 1024;
+
+// Batches queued per worker before the reader waits for it
+const SHARD_QUEUE: usize = 16;
+
+// Position in the input travels along to put rejects back in order
+type ShardRecord = (u64, InputRecord<anyhow::Error>);
 
 #[derive(Debug, Parser)]
 #[command(about = "Interpreter of CSV transactions", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct ProgramArgs {
-    // file name for a valid CSV transaction file
-    filename: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // files of transactions, read one after the other into the same
+    // engine. `-` is stdin and globs are expanded.
+    #[arg(required = true)]
+    filenames: Vec<String>,
+
+    /// Keep accounts and the ledger in this on-disk database instead of
+    /// memory. Re-running with the same database resumes after the last
+    /// committed record of the same inputs, and fails on other inputs.
+    #[arg(long, value_name = "PATH")]
+    store: Option<PathBuf>,
+
+    /// Journal every accepted transaction to this file before applying
+    /// it, replaying whatever it already holds on startup and resuming
+    /// after the last journaled record of the file.
+    #[arg(long, value_name = "PATH", conflicts_with = "store")]
+    journal: Option<PathBuf>,
+
+    /// Restore from this snapshot before replaying only the newer part
+    /// of the journal, and refresh it once all inputs are applied.
+    #[arg(long, value_name = "PATH", requires = "journal")]
+    snapshot: Option<PathBuf>,
+
+    /// Format of the input file
+    #[arg(long, value_enum, default_value_t = InputFormat::Csv)]
+    input_format: InputFormat,
+
+    /// Also read NAME as the transaction type TYPE, e.g. `credit=deposit`.
+    /// Types are matched regardless of case. Can be given more than once.
+    #[arg(long = "alias", value_name = "NAME=TYPE", value_parser = parse_alias)]
+    aliases: Vec<(String, String)>,
+
+    /// Read FIELD (type, client, tx or amount) from another column, given
+    /// by its header or its number counting from 1, e.g. `client=customer_id`.
+    /// Can be given more than once.
+    #[arg(long = "column", value_name = "FIELD=COLUMN", value_parser = parse_column)]
+    columns: Vec<(String, Column)>,
+
+    /// The CSV file has no header, its columns are `type, client, tx, amount`
+    /// unless mapped by number with `--column`
+    #[arg(long)]
+    no_header: bool,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Write every malformed or rejected record to this CSV file, with its
+    /// line number, a reason code, a message and its original fields.
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+
+    /// Stop at the first malformed or rejected record with an error naming
+    /// its line, instead of skipping it. No balances are printed then.
+    #[arg(long)]
+    strict: bool,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Split the input by client over this many worker threads, each
+    /// with its own in-memory engine.
+    #[arg(long, default_value = "1", conflicts_with_all = ["store", "journal"])]
+    workers: NonZeroUsize,
 }
 
-// This is a nice hack to make the CSV reader
-// and serde deserialize directly to the enum.
-// The csv deserializer doesn't directly support
-// flat enum: https://github.com/BurntSushi/rust-csv/issues/211
-#[derive(Debug, Deserialize)]
-struct TransactionWrapper {
-    #[serde(flatten)]
-    pub transaction: Transaction,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    // With a `type, client, tx, amount` header
+    Csv,
+    // One JSON object per line with the same fields
+    Jsonl,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Accept CSV transaction lines over TCP instead of reading a file,
+    /// replying to each one. Prints the accounts on Ctrl-C.
+    Serve(ServeArgs),
+
+    /// Serve an HTTP/JSON API over the engine. Prints the accounts on Ctrl-C.
+    Http(ServeArgs),
 }
 
-fn handle_transactions<R: Read>(reader: R) -> anyhow::Result<TransactionEngine> {
-    let mut csv_reader = ReaderBuilder::new()
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_reader(reader);
-
-    let mut engine = TransactionEngine::default();
-
-    let csv_iterator = csv_reader
-        .deserialize::<TransactionWrapper>()
-        .map(|res| res.map_err(anyhow::Error::from))
-        .map(|res| res.map(|v| v.transaction));
-
-    for result in csv_iterator {
-        // Assumption: ignore invalid and malformed transations
-        if let Ok(transaction) = result {
-            engine.handle(transaction).unwrap_or_else(|err| {
-                eprintln!("could not handle transaction {}: {:#?}", transaction, err)
-            })
-        } else {
-            eprintln!("ignoring invalid CSV line: {:?}", result);
+#[derive(Debug, Args)]
+struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    listen: SocketAddr,
+
+    /// Journal every accepted transaction to this file, replaying it on startup
+    #[arg(long, value_name = "PATH")]
+    journal: Option<PathBuf>,
+
+    /// Restore from this snapshot and the journal tail, refreshing it on shutdown
+    #[arg(long, value_name = "PATH", requires = "journal")]
+    snapshot: Option<PathBuf>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+fn handle_transactions(
+    records: impl Iterator<Item = InputRecord<anyhow::Error>>,
+    engine_args: &EngineArgs,
+    rejects: &mut RejectLog,
+) -> anyhow::Result<TransactionEngine> {
+    let mut engine = engine_args.configure(TransactionEngine::default());
+    feed_transactions(records, &mut engine, rejects)?;
+    Ok(engine)
+}
+
+// Expands the names given on the command line, in order. Globs match
+// in alphabetical order and `-` is stdin.
+fn input_names(patterns: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for pattern in patterns {
+        if pattern == STDIN || !pattern.contains(['*', '?', '[']) {
+            names.push(pattern.clone());
             continue;
         }
+
+        let matched = glob::glob(pattern)?
+            .map(|path| Ok(path?.to_string_lossy().into_owned()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if matched.is_empty() {
+            anyhow::bail!("no files match {}", pattern);
+        }
+        names.extend(matched);
     }
+    Ok(names)
+}
 
-    Ok(engine)
+// Every record of every input, one input after the other, decompressing
+// any that are gzip or zstd. Inputs are all checked up front so a missing
+// one fails the run before anything is applied, but each is only opened
+// once the one before it is done.
+fn read_inputs(
+    names: &[String],
+    format: InputFormat,
+    options: ReadOptions,
+) -> anyhow::Result<impl Iterator<Item = InputRecord<anyhow::Error>> + use<>> {
+    if names.iter().filter(|name| *name == STDIN).count() > 1 {
+        anyhow::bail!("stdin (`{}`) can only be read once", STDIN);
+    }
+    for name in names.iter().filter(|name| *name != STDIN) {
+        File::open(Path::new(name))
+            .map_err(|err| anyhow::anyhow!("could not open {}: {}", name, err))?;
+    }
+
+    let names = names.to_vec();
+    Ok(names.into_iter().flat_map(move |name| {
+        let source = Arc::<str>::from(name.as_str());
+        let records = match open_input(&name) {
+            Ok(reader) => read_transactions(reader, format, options.clone()),
+            // Failed like a read would, see `readable`
+            Err(err) => Box::new(std::iter::once(InputRecord {
+                source: None,
+                line: 0,
+                fields: StringRecord::new(),
+                transaction: Err(err.into()),
+            })),
+        };
+        records.map(move |mut record| {
+            record.source = Some(source.clone());
+            record
+        })
+    }))
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = ProgramArgs::parse();
+fn open_input(name: &str) -> io::Result<Box<dyn Read>> {
+    let reader: Box<dyn Read> = if name == STDIN {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(Path::new(name))?)
+    };
+    compression::decompress(name, reader)
+}
+
+// Both formats parse to the same transactions with the same validation
+fn read_transactions<'r, R: Read + 'r>(
+    reader: R,
+    format: InputFormat,
+    options: ReadOptions,
+) -> Box<dyn Iterator<Item = InputRecord<anyhow::Error>> + 'r> {
+    match format {
+        InputFormat::Csv => Box::new(
+            input::read_csv_with(reader, options).map(|record| record.map_err(anyhow::Error::from)),
+        ),
+        InputFormat::Jsonl => Box::new(
+            input::read_jsonl_with(reader, options)
+                .map(|record| record.map_err(anyhow::Error::from)),
+        ),
+    }
+}
+
+// Fails on a record whose input couldn't be read, e.g. a truncated
+// archive, rather than being malformed. Nothing after it can be read
+// either, so the run stops instead of printing partial balances.
+fn readable(record: InputRecord<anyhow::Error>) -> anyhow::Result<InputRecord<anyhow::Error>> {
+    let Err(err) = &record.transaction else {
+        return Ok(record);
+    };
+
+    let io_failed = err.is::<io::Error>()
+        || err
+            .downcast_ref::<csv::Error>()
+            .is_some_and(|err| matches!(err.kind(), csv::ErrorKind::Io(_)))
+        || err
+            .downcast_ref::<serde_json::Error>()
+            .is_some_and(serde_json::Error::is_io);
+    if io_failed {
+        let source = record.source.as_deref().unwrap_or("input");
+        if record.line == 0 {
+            anyhow::bail!("could not read {}: {}", source, err);
+        }
+        anyhow::bail!(
+            "could not read {} after line {}: {}",
+            source,
+            record.line,
+            err
+        );
+    }
+    Ok(record)
+}
+
+// Splits `--alias NAME=TYPE`, checking the type exists
+fn parse_alias(value: &str) -> Result<(String, String), String> {
+    let (alias, type_name) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=TYPE, got `{}`", value))?;
+    if Transaction::type_name(type_name.trim()).is_none() {
+        return Err(UnknownType(type_name.trim().to_string()).to_string());
+    }
+    Ok((alias.trim().to_string(), type_name.trim().to_string()))
+}
+
+// Splits `--column FIELD=COLUMN`, checking the field exists
+fn parse_column(value: &str) -> Result<(String, Column), String> {
+    let (field, column) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=COLUMN, got `{}`", value))?;
+    let field = field.trim();
+    if !FIELDS.contains(&field) {
+        return Err(UnknownField(field.to_string()).to_string());
+    }
+    let Ok(column) = column.parse();
+    Ok((field.to_string(), column))
+}
 
-    let file = File::open(Path::new(&args.filename))?;
-    let state = handle_transactions(file)?;
+// Transactions of different clients never interact, so each client's
+// records can go to its own worker engine as long as they stay in order.
+// The per-worker accounts and ledgers are merged once all are done.
+//
+// Rejects are collected and only written, in input order, at the end.
+fn handle_transactions_sharded(
+    records: impl Iterator<Item = InputRecord<anyhow::Error>>,
+    workers: NonZeroUsize,
+    engine_args: &EngineArgs,
+    rejects: &mut RejectLog,
+) -> anyhow::Result<TransactionEngine> {
+    let workers = workers.get();
+    let strict = rejects.validation() == Validation::Strict;
+
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let (sender, receiver) = mpsc::sync_channel::<Vec<ShardRecord>>(SHARD_QUEUE);
+            senders.push(sender);
+            handles.push(scope.spawn(move || {
+                let mut engine = engine_args.configure(TransactionEngine::default());
+                let mut rejected = Vec::new();
+                for (position, record) in receiver.into_iter().flatten() {
+                    // Keeps draining so the reader is never left sending to no one
+                    if strict && !rejected.is_empty() {
+                        continue;
+                    }
+                    if let Err(reject) = engine.handle_record(record) {
+                        rejected.push((position, reject));
+                    }
+                }
+                (engine, rejected)
+            }));
+        }
+
+        let mut all_rejected = Vec::new();
+        let mut batches = (0..workers)
+            .map(|_| Vec::with_capacity(SHARD_BATCH))
+            .collect::<Vec<_>>();
+
+        // Client of every deposit, withdrawal and administrative operation
+        // id so far. Workers only see their own clients' transactions, so
+        // one that reuses or refers to another worker's id would be judged
+        // differently than in a serial run.
+        let mut id_clients = HashMap::new();
+
+        for (position, record) in (0u64..).zip(records) {
+            let record = readable(record)?;
+            let transaction = match &record.transaction {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    let reject = Reject::malformed(record.source, record.line, record.fields, err);
+                    all_rejected.push((position, reject));
+                    if strict {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            let client_id = transaction.client_id();
+            let shard = usize::from(client_id) % workers;
+            let transaction_id = transaction.transaction_id();
+            match id_clients.get(&transaction_id) {
+                Some(&first) if usize::from(first) % workers != shard => anyhow::bail!(
+                    "transaction {} is used by both client {} and client {}, \
+                     transaction ids must be unique to use --workers",
+                    transaction_id,
+                    first,
+                    client_id
+                ),
+                Some(_) => {}
+                None => {
+                    let recorded = transaction.is_admin()
+                        || matches!(
+                            transaction,
+                            Transaction::Deposit { .. } | Transaction::Withdraw { .. }
+                        );
+                    if recorded {
+                        id_clients.insert(transaction_id, client_id);
+                    }
+                }
+            }
+
+            batches[shard].push((position, record));
+            if batches[shard].len() == SHARD_BATCH {
+                let batch = std::mem::replace(&mut batches[shard], Vec::with_capacity(SHARD_BATCH));
+                senders[shard].send(batch)?;
+            }
+        }
 
-    let mut writer = WriterBuilder::new()
-        .flexible(true)
-        .from_writer(std::io::stdout());
+        // Dropping the senders lets the workers finish
+        for (sender, batch) in senders.into_iter().zip(batches) {
+            sender.send(batch)?;
+        }
+
+        let mut merged = TransactionEngine::default();
+        for handle in handles {
+            let (engine, rejected) = handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("worker thread panicked"))?;
+            merged.client_accounts.extend(engine.client_accounts);
+            all_rejected.extend(rejected);
+
+            merged.ledger.extend(engine.ledger);
+        }
+
+        // Shards don't interact, so the earliest reject is also where
+        // a serial strict run would have stopped
+        all_rejected.sort_by_key(|(position, _)| *position);
+        for (_, reject) in all_rejected {
+            rejects.report(reject)?;
+        }
+        Ok(merged)
+    })
+}
+
+// Applies every record past the storage checkpoint to the engine,
+// committing as it goes so an interrupted run can pick up from there.
+// Fails before applying anything if the checkpoint was left by other inputs.
+fn feed_transactions<A: AccountRepository, L: LedgerRepository>(
+    records: impl Iterator<Item = InputRecord<anyhow::Error>>,
+    engine: &mut TransactionEngine<A, L>,
+    rejects: &mut RejectLog,
+) -> anyhow::Result<()> {
+    let checkpoint = engine.checkpoint()?;
+    let mut last = InputPosition::default();
+
+    for record in records {
+        let record = readable(record)?;
+        let position = record.position(last.record + 1);
+        if position.record < checkpoint.record {
+            last = position;
+            continue;
+        }
+        if position.record == checkpoint.record {
+            if position != checkpoint {
+                anyhow::bail!(
+                    "the previous run got to {} but this run's {} differs, \
+                     resume with the same inputs in the same order",
+                    checkpoint,
+                    position
+                );
+            }
+            last = position;
+            continue;
+        }
 
-    for item in state.client_accounts.values() {
-        writer.serialize(item)?;
+        last = position.clone();
+        if let Err(reject) = engine.handle_record_at(record, position) {
+            rejects.report(reject)?;
+        }
+
+        if last.record % COMMIT_INTERVAL == 0 {
+            engine.commit(&last)?;
+        }
     }
 
+    if last.record < checkpoint.record {
+        anyhow::bail!(
+            "the previous run got to {} but this run's inputs end at record {}, \
+             resume with the same inputs in the same order",
+            checkpoint,
+            last.record
+        );
+    }
+    engine.commit(&last)?;
     Ok(())
 }
 
+// In-memory engine, rebuilt from the journal and snapshot if given
+fn recover_engine(
+    journal: Option<PathBuf>,
+    snapshot: Option<PathBuf>,
+    engine_args: &EngineArgs,
+) -> anyhow::Result<TransactionEngine> {
+    let mut engine = engine_args.configure(TransactionEngine::default());
+    let Some(journal) = journal else {
+        return Ok(engine);
+    };
+
+    let replayed = match snapshot {
+        Some(snapshot) => engine.restore(snapshot, journal)?,
+        None => engine.recover(journal)?,
+    };
+    eprintln!("recovered {} transactions from the journal", replayed);
+    Ok(engine)
+}
+
+fn run_server(command: Command) -> anyhow::Result<()> {
+    let (Command::Serve(args) | Command::Http(args)) = &command;
+    let engine = recover_engine(args.journal.clone(), args.snapshot.clone(), &args.engine)?;
+    let engine: SharedEngine = Arc::new(engine.into());
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(args.listen).await?;
+        eprintln!("listening on {}", listener.local_addr()?);
+
+        let shutdown = async {
+            let _ = tokio::signal::ctrl_c().await;
+        };
+        match command {
+            Command::Serve(_) => {
+                serve::serve(listener, engine.clone(), shutdown).await?;
+            }
+            Command::Http(_) => {
+                axum::serve(listener, api::router(engine.clone()))
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+            }
+        }
+
+        let mut engine = engine.lock().await;
+        engine.commit(&InputPosition::default())?;
+        engine.save_snapshot()?;
+        let output = OutputArgs {
+            fraud_flags: !args.engine.fraud_rules.is_empty(),
+            ..OutputArgs::default()
+        };
+        write_accounts(&engine, &output)
+    })
+}
+
+fn write_accounts<A: AccountRepository, L: LedgerRepository>(
+    engine: &TransactionEngine<A, L>,
+    args: &OutputArgs,
+) -> anyhow::Result<()> {
+    let mut accounts = engine.client_accounts.all()?;
+    output::sort(&mut accounts, args.sort_by, args.desc);
+    output::write(
+        &accounts,
+        args.output_format,
+        args.fraud_flags,
+        std::io::stdout().lock(),
+    )
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = ProgramArgs::parse();
+    args.output.fraud_flags = !args.engine.fraud_rules.is_empty();
+
+    if let Some(command) = args.command {
+        return run_server(command);
+    }
+    let names = input_names(&args.filenames)?;
+    let format = args.input_format;
+    let mut options = ReadOptions::default();
+    options.headerless = args.no_header;
+    for (alias, type_name) in &args.aliases {
+        options.aliases.insert(alias, type_name)?;
+    }
+    for (field, column) in &args.columns {
+        options.map_column(field, column.clone())?;
+    }
+
+    let validation = if args.strict {
+        Validation::Strict
+    } else {
+        Validation::Lenient
+    };
+    let mut rejects = match &args.rejects {
+        Some(path) => RejectLog::create(path)?,
+        None => RejectLog::default(),
+    }
+    .with_validation(validation);
+
+    match (args.store, args.journal) {
+        (Some(path), _) => {
+            let storage = DiskStorage::open(path)?;
+            let mut engine = args
+                .engine
+                .configure(TransactionEngine::new(storage.clone(), storage));
+            feed_transactions(
+                read_inputs(&names, format, options)?,
+                &mut engine,
+                &mut rejects,
+            )?;
+            rejects.finish()?;
+            write_accounts(&engine, &args.output)
+        }
+        (None, journal @ Some(_)) => {
+            let mut engine = recover_engine(journal, args.snapshot, &args.engine)?;
+            feed_transactions(
+                read_inputs(&names, format, options)?,
+                &mut engine,
+                &mut rejects,
+            )?;
+            engine.save_snapshot()?;
+            rejects.finish()?;
+            write_accounts(&engine, &args.output)
+        }
+        (None, None) => {
+            let records = read_inputs(&names, format, options)?;
+            let state = if args.workers.get() > 1 {
+                handle_transactions_sharded(records, args.workers, &args.engine, &mut rejects)?
+            } else {
+                handle_transactions(records, &args.engine, &mut rejects)?
+            };
+            rejects.finish()?;
+            write_accounts(&state, &args.output)
+        }
+    }
+}
+
 // I guess these should be proper integration tests, but this will do
 #[cfg(test)]
 mod tests {
     use anyhow::Context;
+    use transaction::{FraudAction, Money};
 
     use super::*;
 
@@ -83,20 +619,28 @@ deposit, 1, 1, 1.0
 deposit, 2, 2, 2.0
 deposit, 1, 3, 2.0"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 3.0);
+        assert_eq!(client_one.available, "3.0".parse::<Money>()?);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 2.0);
+        assert_eq!(client_two.available, "2.0".parse::<Money>()?);
         assert!(!client_two.locked);
         Ok(())
     }
@@ -108,37 +652,62 @@ deposit, 1, 1, 1.0
 deposit   , 2, 2, 2.0
     deposit, 1, 3, 2.0"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 3.0);
+        assert_eq!(client_one.available, "3.0".parse::<Money>()?);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 2.0);
+        assert_eq!(client_two.available, "2.0".parse::<Money>()?);
         assert!(!client_two.locked);
         Ok(())
     }
 
-    // Ideally, this would work but I dont
-    // have time to write my own deserializer
-    // with case-insensitiveness
     #[test]
-    fn parser_fails_wrong_spelling_deposit() -> anyhow::Result<()> {
+    fn parser_ignores_case_deposit() -> anyhow::Result<()> {
         let test_str = r#"type, client, tx, amount
 Deposit, 1, 1, 1.0
 deposiT, 2, 2, 2.0
 DEPOSIT, 1, 3, 2.0"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
-        assert!(!state.client_accounts.contains_key(&1u16));
-        assert!(!state.client_accounts.contains_key(&2u16));
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "3.0".parse::<Money>()?);
+        assert!(!client_one.locked);
+
+        let client_two = state
+            .client_accounts
+            .get(&2u16)
+            .context("could not get client")?;
+        assert_eq!(client_two.available, "2.0".parse::<Money>()?);
+        assert!(!client_two.locked);
         Ok(())
     }
 
@@ -148,20 +717,28 @@ DEPOSIT, 1, 3, 2.0"#;
 withdrawal, 1, 4, 1.5
 withdrawal, 2, 5, 3.0"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
+        assert_eq!(client_one.available, Money::ZERO);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
+        assert_eq!(client_two.available, Money::ZERO);
         assert!(!client_two.locked);
         Ok(())
     }
@@ -171,34 +748,64 @@ withdrawal, 2, 5, 3.0"#;
         let test_str = r#"type, client, tx, amount
     withdrawal, 1, 4, 1.5
 withdraw    , 2, 5, 3.0"#;
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
+        assert_eq!(client_one.available, Money::ZERO);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
+        assert_eq!(client_two.available, Money::ZERO);
         assert!(!client_two.locked);
         Ok(())
     }
 
     #[test]
-    fn parser_fails_wrong_spelling_withdrawal() -> anyhow::Result<()> {
+    fn parser_ignores_case_withdrawal() -> anyhow::Result<()> {
         let test_str = r#"type, client, tx, amount
+deposit, 1, 10, 5
+deposit, 2, 20, 5
 Withdrawal, 1, 1, 1.0
 WITHDRAWAL, 2, 2, 2.0
 withdrawaL, 1, 3, 2.0"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
-        assert!(!state.client_accounts.contains_key(&1u16));
-        assert!(!state.client_accounts.contains_key(&2u16));
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "2.0".parse::<Money>()?);
+        assert!(!client_one.locked);
+
+        let client_two = state
+            .client_accounts
+            .get(&2u16)
+            .context("could not get client")?;
+        assert_eq!(client_two.available, "3.0".parse::<Money>()?);
+        assert!(!client_two.locked);
         Ok(())
     }
 
@@ -209,22 +816,30 @@ deposit, 1, 100, 50
 deposit, 2, 42, 50
 dispute, 1, 100,
 dispute, 2, 42,"#;
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
-        assert_eq!(client_one.held, 50.0);
+        assert_eq!(client_one.available, Money::ZERO);
+        assert_eq!(client_one.held, "50.0".parse::<Money>()?);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
-        assert_eq!(client_two.held, 50.0);
+        assert_eq!(client_two.available, Money::ZERO);
+        assert_eq!(client_two.held, "50.0".parse::<Money>()?);
         assert!(!client_two.locked);
         Ok(())
     }
@@ -237,28 +852,36 @@ deposit, 2, 42, 50
     dispute, 1, 100,
 dispute     , 2, 42,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
-        assert_eq!(client_one.held, 50.0);
+        assert_eq!(client_one.available, Money::ZERO);
+        assert_eq!(client_one.held, "50.0".parse::<Money>()?);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
-        assert_eq!(client_two.held, 50.0);
+        assert_eq!(client_two.available, Money::ZERO);
+        assert_eq!(client_two.held, "50.0".parse::<Money>()?);
         assert!(!client_two.locked);
         Ok(())
     }
 
     #[test]
-    fn parser_fails_wrong_spelling_dispute() -> anyhow::Result<()> {
+    fn parser_ignores_case_dispute() -> anyhow::Result<()> {
         let test_str = r#"type, client, tx, amount
 deposit, 1, 1, 50
 deposit, 2, 2, 50
@@ -267,32 +890,39 @@ Dispute, 1, 1,
 disputE, 2, 2,
 DISPUTE, 3, 3,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 50.0);
-        assert_eq!(client_one.held, 0.0);
+        assert_eq!(client_one.available, Money::ZERO);
+        assert_eq!(client_one.held, "50.0".parse::<Money>()?);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 50.0);
-        assert_eq!(client_two.held, 0.0);
+        assert_eq!(client_two.available, Money::ZERO);
+        assert_eq!(client_two.held, "50.0".parse::<Money>()?);
         assert!(!client_two.locked);
 
         let client_three = state
             .client_accounts
             .get(&3u16)
             .context("could not get client")?;
-        assert_eq!(client_three.available, 50.0);
-        assert_eq!(client_three.held, 0.0);
+        assert_eq!(client_three.available, Money::ZERO);
+        assert_eq!(client_three.held, "50.0".parse::<Money>()?);
         assert!(!client_three.locked);
-
         Ok(())
     }
 
@@ -306,22 +936,30 @@ dispute, 2, 42,
 resolve, 1, 100,
 resolve, 2, 42,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 50.0);
-        assert_eq!(client_one.held, 0.0);
+        assert_eq!(client_one.available, "50.0".parse::<Money>()?);
+        assert_eq!(client_one.held, Money::ZERO);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 50.0);
-        assert_eq!(client_two.held, 0.0);
+        assert_eq!(client_two.available, "50.0".parse::<Money>()?);
+        assert_eq!(client_two.held, Money::ZERO);
         assert!(!client_two.locked);
         Ok(())
     }
@@ -336,28 +974,36 @@ dispute, 2, 2,
     resolve, 1, 1,
 resolve     , 2, 2,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 100.0);
-        assert_eq!(client_one.held, 0.0);
+        assert_eq!(client_one.available, "100.0".parse::<Money>()?);
+        assert_eq!(client_one.held, Money::ZERO);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 42.0);
-        assert_eq!(client_two.held, 0.0);
+        assert_eq!(client_two.available, "42.0".parse::<Money>()?);
+        assert_eq!(client_two.held, Money::ZERO);
         assert!(!client_two.locked);
         Ok(())
     }
 
     #[test]
-    fn parser_fails_wrong_spelling_resolve() -> anyhow::Result<()> {
+    fn parser_ignores_case_resolve() -> anyhow::Result<()> {
         let test_str = r#"type, client, tx, amount
 deposit, 1, 1, 100
 deposit, 2, 2, 42
@@ -369,32 +1015,39 @@ Resolve, 1, 1,
 resolvE, 2, 2,
 RESOLVE, 3, 3,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
-        assert_eq!(client_one.held, 100.0);
+        assert_eq!(client_one.available, "100.0".parse::<Money>()?);
+        assert_eq!(client_one.held, Money::ZERO);
         assert!(!client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
-        assert_eq!(client_two.held, 42.0);
+        assert_eq!(client_two.available, "42.0".parse::<Money>()?);
+        assert_eq!(client_two.held, Money::ZERO);
         assert!(!client_two.locked);
 
         let client_three = state
             .client_accounts
             .get(&3u16)
             .context("could not get client")?;
-        assert_eq!(client_three.available, 0.0);
-        assert_eq!(client_three.held, 3.0);
+        assert_eq!(client_three.available, "3.0".parse::<Money>()?);
+        assert_eq!(client_three.held, Money::ZERO);
         assert!(!client_three.locked);
-
         Ok(())
     }
 
@@ -408,20 +1061,28 @@ dispute, 2, 42,
 chargeback, 1, 100,
 chargeback, 2, 42,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
+        assert_eq!(client_one.available, Money::ZERO);
         assert!(client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
+        assert_eq!(client_two.available, Money::ZERO);
         assert!(client_two.locked);
         Ok(())
     }
@@ -436,58 +1097,775 @@ dispute, 2, 42,
     chargeback, 1, 100,
 chargeback     , 2, 42,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 0.0);
-        assert_eq!(client_one.held, 0.0);
+        assert_eq!(client_one.available, Money::ZERO);
+        assert_eq!(client_one.held, Money::ZERO);
         assert!(client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 0.0);
-        assert_eq!(client_two.held, 0.0);
+        assert_eq!(client_two.available, Money::ZERO);
+        assert_eq!(client_two.held, Money::ZERO);
         assert!(client_two.locked);
         Ok(())
     }
 
     #[test]
-    fn parser_fails_wrong_spelling_chargeback() -> anyhow::Result<()> {
+    fn parser_ignores_case_chargeback() -> anyhow::Result<()> {
         let test_str = r#"type, client, tx, amount
 deposit, 1, 1, 100
 deposit, 2, 2, 42
 deposit, 3, 3, 3
+dispute, 1, 1,
+dispute, 2, 2,
+dispute, 3, 3,
 Chargeback, 1, 1,
 chargebacK, 2, 2,
 CHARGEBACK, 3, 3,"#;
 
-        let state = handle_transactions(test_str.as_bytes())?;
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
 
         let client_one = state
             .client_accounts
             .get(&1u16)
             .context("could not get client")?;
-        assert_eq!(client_one.available, 100.0);
-        assert!(!client_one.locked);
+        assert_eq!(client_one.available, Money::ZERO);
+        assert_eq!(client_one.held, Money::ZERO);
+        assert!(client_one.locked);
 
         let client_two = state
             .client_accounts
             .get(&2u16)
             .context("could not get client")?;
-        assert_eq!(client_two.available, 42.0);
-        assert!(!client_two.locked);
+        assert_eq!(client_two.available, Money::ZERO);
+        assert_eq!(client_two.held, Money::ZERO);
+        assert!(client_two.locked);
 
         let client_three = state
             .client_accounts
             .get(&3u16)
             .context("could not get client")?;
-        assert_eq!(client_three.available, 3.0);
-        assert!(!client_three.locked);
+        assert_eq!(client_three.available, Money::ZERO);
+        assert_eq!(client_three.held, Money::ZERO);
+        assert!(client_three.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_sums_amounts_exactly() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount
+deposit, 1, 1, 0.1
+deposit, 1, 2, 0.2
+withdrawal, 1, 3, 0.3
+deposit, 1, 4, 0.0001
+deposit, 2, 5, 1.00001"#;
+
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "0.0001".parse::<Money>()?);
+
+        // Too many decimal places is malformed, not rounded
+        assert!(!state.client_accounts.contains_key(&2u16));
+        Ok(())
+    }
+
+    #[test]
+    fn parser_happy_path_withdrawal_dispute() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount
+deposit, 1, 1, 100
+deposit, 2, 2, 100
+withdrawal, 1, 3, 40
+withdrawal, 2, 4, 40
+dispute, 1, 3,
+dispute, 2, 4,
+resolve, 1, 3,
+chargeback, 2, 4,"#;
+
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "60".parse::<Money>()?);
+        assert_eq!(client_one.held, Money::ZERO);
+        assert!(!client_one.locked);
+
+        let client_two = state
+            .client_accounts
+            .get(&2u16)
+            .context("could not get client")?;
+        assert_eq!(client_two.available, "100".parse::<Money>()?);
+        assert_eq!(client_two.held, Money::ZERO);
+        assert!(client_two.locked);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_resumes_from_disk_checkpoint() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("store.redb");
+
+        let first_half = r#"type, client, tx, amount
+deposit, 1, 1, 10
+withdrawal, 1, 2, 4"#;
+        let whole_file = r#"type, client, tx, amount
+deposit, 1, 1, 10
+withdrawal, 1, 2, 4
+deposit, 1, 3, 6
+withdrawal, 1, 4, 11"#;
+
+        // An earlier run only got through the first records
+        {
+            let storage = DiskStorage::open(&path)?;
+            let mut engine = TransactionEngine::new(storage.clone(), storage);
+            let records = read_transactions(
+                first_half.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            );
+            feed_transactions(records, &mut engine, &mut RejectLog::default())?;
+        }
+
+        // Without skipping them the final withdrawal would now succeed
+        let storage = DiskStorage::open(&path)?;
+        let mut engine = TransactionEngine::new(storage.clone(), storage);
+        assert_eq!(engine.checkpoint()?.record, 2);
+        let records = read_transactions(
+            whole_file.as_bytes(),
+            InputFormat::Csv,
+            ReadOptions::default(),
+        );
+        feed_transactions(records, &mut engine, &mut RejectLog::default())?;
+        assert_eq!(engine.checkpoint()?.record, 4);
+
+        let accounts = AccountRepository::all(&engine.client_accounts)?;
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].available, "1".parse::<Money>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_refuses_other_inputs_against_a_store() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("store.redb");
+        let day1 = dir.path().join("day1.csv");
+        let day2 = dir.path().join("day2.csv");
+        std::fs::write(
+            &day1,
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,10\n",
+        )?;
+        std::fs::write(
+            &day2,
+            "type,client,tx,amount\ndeposit,2,3,7\ndeposit,2,4,7\ndeposit,2,5,7\n",
+        )?;
+        let names = |paths: &[&Path]| {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let feed =
+            |paths: &[&Path]| -> anyhow::Result<TransactionEngine<DiskStorage, DiskStorage>> {
+                let storage = DiskStorage::open(&path)?;
+                let mut engine = TransactionEngine::new(storage.clone(), storage);
+                let records = read_inputs(&names(paths), InputFormat::Csv, ReadOptions::default())?;
+                feed_transactions(records, &mut engine, &mut RejectLog::default())?;
+                Ok(engine)
+            };
+
+        feed(&[&day1])?;
+
+        // Skipping two records of day2 would drop two of its deposits
+        let err = feed(&[&day2])
+            .err()
+            .context("day2 was fed as a resumed day1")?;
+        assert!(err.to_string().contains("same inputs in the same order"));
+
+        let engine = feed(&[&day1, &day2])?;
+        let client =
+            AccountRepository::find(&engine.client_accounts, 2)?.context("could not get client")?;
+        assert_eq!(client.available, "21".parse::<Money>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_resumes_after_journaled_records() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = dir.path().join("journal.jsonl");
+
+        let input = r#"type, client, tx, amount
+withdrawal, 1, 1, 10
+deposit, 1, 2, 100"#;
+
+        // Fed again, the withdrawal would pass on top of the replayed deposit
+        for _ in 0..2 {
+            let mut engine = recover_engine(Some(journal.clone()), None, &EngineArgs::default())?;
+            let records =
+                read_transactions(input.as_bytes(), InputFormat::Csv, ReadOptions::default());
+            feed_transactions(records, &mut engine, &mut RejectLog::default())?;
+
+            let client = engine
+                .client_accounts
+                .get(&1)
+                .context("could not get client")?;
+            assert_eq!(client.available, "100".parse::<Money>()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parser_refuses_other_inputs_against_a_journal() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = dir.path().join("journal.jsonl");
+        let run_a = dir.path().join("a.csv");
+        let run_b = dir.path().join("b.csv");
+        std::fs::write(&run_a, "type,client,tx,amount\ndeposit,1,1,10\n")?;
+        std::fs::write(
+            &run_b,
+            "type,client,tx,amount\ndeposit,2,2,7\ndeposit,2,3,7\n",
+        )?;
+        let feed = |path: &Path| -> anyhow::Result<TransactionEngine> {
+            let mut engine = recover_engine(Some(journal.clone()), None, &EngineArgs::default())?;
+            let names = [path.to_string_lossy().into_owned()];
+            let records = read_inputs(&names, InputFormat::Csv, ReadOptions::default())?;
+            feed_transactions(records, &mut engine, &mut RejectLog::default())?;
+            Ok(engine)
+        };
+
+        feed(&run_a)?;
+
+        // Resuming would skip b.csv's first deposit as if it were a.csv's
+        let err = feed(&run_b)
+            .err()
+            .context("b.csv was fed as a resumed a.csv")?;
+        assert!(err.to_string().contains("a.csv line 2"));
+
+        let engine = feed(&run_a)?;
+        assert!(!engine.client_accounts.contains_key(&2));
+        Ok(())
+    }
+
+    #[test]
+    fn parser_sharded_matches_serial() -> anyhow::Result<()> {
+        // Enough records to fill several batches per worker
+        let mut input = String::from("type, client, tx, amount\n");
+        for tx in 0..3000u64 {
+            let client = tx / 5 % 7;
+            input += &match tx % 5 {
+                0 | 1 => format!("deposit, {}, {}, 1.5\n", client, tx),
+                2 => format!("withdrawal, {}, {}, 2.25\n", client, tx),
+                3 => format!("dispute, {}, {}, \n", client, tx - 3),
+                _ => format!("chargeback, {}, {}, \n", client, tx - 4),
+            };
+        }
+
+        let serial = handle_transactions(
+            read_transactions(input.as_bytes(), InputFormat::Csv, ReadOptions::default()),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+        let workers = NonZeroUsize::new(3).context("non-zero")?;
+        let records = read_transactions(input.as_bytes(), InputFormat::Csv, ReadOptions::default());
+        let sharded = handle_transactions_sharded(
+            records,
+            workers,
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        assert_eq!(sharded.client_accounts, serial.client_accounts);
+        assert_eq!(sharded.ledger, serial.ledger);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_sharded_fails_on_reused_ids() -> anyhow::Result<()> {
+        let workers = NonZeroUsize::new(2).context("non-zero")?;
+        let records = |input: &'static str| {
+            read_transactions(input.as_bytes(), InputFormat::Csv, ReadOptions::default())
+        };
+        let sharded = |input| {
+            handle_transactions_sharded(
+                records(input),
+                workers,
+                &EngineArgs::default(),
+                &mut RejectLog::default(),
+            )
+        };
+
+        for reused in [
+            // Accepted by both workers
+            "type, client, tx, amount\ndeposit, 1, 5, 10\ndeposit, 2, 5, 20",
+            // Serially a duplicate then a client mismatch, the second
+            // worker would find too little funds and an unknown transaction
+            "type, client, tx, amount\ndeposit, 1, 5, 5\nwithdrawal, 2, 5, 3\ndispute, 2, 5,",
+            // Serially accepted, as the first use was rejected
+            "type, client, tx, amount\nwithdrawal, 1, 5, 10\ndeposit, 2, 5, 20",
+        ] {
+            let err = sharded(reused).err().context("reused id was let through")?;
+            assert!(err.to_string().contains("transaction 5"));
+        }
+
+        // Clients on the same worker catch it themselves
+        let same_worker =
+            "type, client, tx, amount\ndeposit, 1, 5, 5\nwithdrawal, 3, 5, 3\ndispute, 3, 5,";
+        let serial = handle_transactions(
+            records(same_worker),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+        let sharded = sharded(same_worker)?;
+        assert_eq!(sharded.client_accounts, serial.client_accounts);
+        assert_eq!(sharded.ledger, serial.ledger);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_happy_path_jsonl() -> anyhow::Result<()> {
+        let test_str = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 3.5}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "1.25"}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 0.00001}
+not json
+{"type": "dispute", "client": 1, "tx": 2}"#;
+
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Jsonl,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "2.25".parse::<Money>()?);
+        assert_eq!(client_one.held, "1.25".parse::<Money>()?);
+        assert!(!state.ledger.contains_key(&3));
+        Ok(())
+    }
+
+    #[test]
+    fn parser_writes_rejects() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let test_str = r#"type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 2, 2, 5.0
+deposit, 1, 1, 1.0
+bogus, 1, 3, 1.0
+dispute, 1, 9,"#;
+
+        for workers in [1, 2] {
+            let path = dir.path().join(format!("rejects-{}.csv", workers));
+            let mut rejects = RejectLog::create(&path)?;
+            let workers = NonZeroUsize::new(workers).context("non-zero")?;
+            let records = read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            );
+            handle_transactions_sharded(records, workers, &EngineArgs::default(), &mut rejects)?;
+            rejects.finish()?;
+
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(&path)?;
+            let rows = reader
+                .records()
+                .map(|row| {
+                    let row = row?;
+                    let fields = row.iter().skip(4).collect::<Vec<_>>().join(",");
+                    Ok((row[1].to_string(), row[2].to_string(), fields))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let row = |line: &str, code: &str, fields: &str| {
+                (line.to_string(), code.to_string(), fields.to_string())
+            };
+            assert_eq!(
+                rows,
+                vec![
+                    row("3", "insufficient_funds", "withdrawal,2,2,5.0"),
+                    row("4", "duplicate_transaction", "deposit,1,1,1.0"),
+                    row("5", "malformed", "bogus,1,3,1.0"),
+                    row("6", "unknown_transaction", "dispute,1,9,"),
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parser_strict_stops_at_first_invalid() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 1.0
+withdrawal, 2, 3, 5.0
+oops, 1
+withdrawal, 1, 4, 5.0"#;
+
+        let strict = || RejectLog::default().with_validation(Validation::Strict);
+        let err = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut strict(),
+        )
+        .err()
+        .context("strict run fails")?;
+        let reject = err
+            .downcast_ref::<Reject>()
+            .context("fails with a reject")?;
+        assert_eq!((reject.line, reject.code), (4, "insufficient_funds"));
+
+        // Workers stop too, the earliest line is still the one reported
+        for workers in [2, 3] {
+            let workers = NonZeroUsize::new(workers).context("non-zero")?;
+            let records = read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            );
+            let err = handle_transactions_sharded(
+                records,
+                workers,
+                &EngineArgs::default(),
+                &mut strict(),
+            )
+            .err()
+            .context("strict run fails")?;
+            let reject = err
+                .downcast_ref::<Reject>()
+                .context("fails with a reject")?;
+            assert_eq!((reject.line, reject.code), (4, "insufficient_funds"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parser_reads_aliases() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount
+Credit, 1, 1, 5.0
+DEBIT, 1, 2, 1.5
+refund, 1, 3, 1.0"#;
+
+        assert!(parse_alias("refund=payback").is_err());
+        assert!(parse_alias("refund").is_err());
+
+        let mut options = ReadOptions::default();
+        for alias in ["credit=deposit", "debit = Withdrawal"] {
+            let (alias, type_name) = parse_alias(alias).map_err(anyhow::Error::msg)?;
+            options.aliases.insert(&alias, &type_name)?;
+        }
+        let state = handle_transactions(
+            read_transactions(test_str.as_bytes(), InputFormat::Csv, options),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "3.5".parse::<Money>()?);
+        assert!(!state.ledger.contains_key(&3));
+        Ok(())
+    }
+
+    #[test]
+    fn parser_reads_admin_operations() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount, operator, reason
+deposit, 1, 1, 5.0, ,
+deposit, 1, 6, 3.0, ,
+dispute, 1, 1, , ,
+chargeback, 1, 1, , ,
+unfreeze, 1, 2, , alice, fraud review cleared
+withdrawal, 1, 3, 1.0, ,
+freeze, 1, 4, , ,
+Close, 1, 5, , alice, requested by the client"#;
+
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "2.0".parse::<Money>()?);
+        assert!(client_one.locked && client_one.closed);
+
+        // The freeze without an operator is malformed
+        assert!(state.ledger.contains_key(&2));
+        assert!(!state.ledger.contains_key(&4));
+        assert!(state.ledger.contains_key(&5));
+        Ok(())
+    }
+
+    #[test]
+    fn parser_runs_fraud_rules() -> anyhow::Result<()> {
+        let test_str = r#"type, client, tx, amount
+deposit, 1, 1, 10.0
+withdrawal, 1, 2, 10.0
+dispute, 1, 1,
+deposit, 1, 3, 5.0
+withdrawal, 1, 4, 1.0"#;
+
+        let engine_args = EngineArgs {
+            fraud_rules: vec![engine::FraudRuleArg::DepositWithdrawDispute(
+                FraudAction::Lock,
+            )],
+            ..EngineArgs::default()
+        };
+        let state = handle_transactions(
+            read_transactions(
+                test_str.as_bytes(),
+                InputFormat::Csv,
+                ReadOptions::default(),
+            ),
+            &engine_args,
+            &mut RejectLog::default(),
+        )?;
+
+        // Deposits still go through on the locked account, withdrawals don't
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert!(client_one.locked);
+        assert_eq!(client_one.available, "5.0".parse::<Money>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_maps_columns() -> anyhow::Result<()> {
+        let test_str = r#"2024-01-01, 1, 5.0, Deposit, 1
+2024-01-02, 2, 1.5, withdrawal, 1"#;
+
+        assert!(parse_column("customer=2").is_err());
+        assert!(parse_column("client").is_err());
+
+        let mut options = ReadOptions::default();
+        options.headerless = true;
+        for column in ["tx=2", "amount=3", "type=4", "client = 5"] {
+            let (field, column) = parse_column(column).map_err(anyhow::Error::msg)?;
+            options.map_column(&field, column)?;
+        }
+        let state = handle_transactions(
+            read_transactions(test_str.as_bytes(), InputFormat::Csv, options),
+            &EngineArgs::default(),
+            &mut RejectLog::default(),
+        )?;
+
+        let client_one = state
+            .client_accounts
+            .get(&1u16)
+            .context("could not get client")?;
+        assert_eq!(client_one.available, "3.5".parse::<Money>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_maps_swapped_columns() -> anyhow::Result<()> {
+        // The file's `client` and `tx` columns are the wrong way round
+        let test_str = r#"type, client, tx, amount
+deposit, 7, 1, 2.0
+deposit, 8, 1, 3.0"#;
+
+        for columns in [["client=tx", "tx=client"], ["tx=client", "client=tx"]] {
+            let mut options = ReadOptions::default();
+            for column in columns {
+                let (field, column) = parse_column(column).map_err(anyhow::Error::msg)?;
+                options.map_column(&field, column)?;
+            }
+            let state = handle_transactions(
+                read_transactions(test_str.as_bytes(), InputFormat::Csv, options),
+                &EngineArgs::default(),
+                &mut RejectLog::default(),
+            )?;
+
+            let client_one = state
+                .client_accounts
+                .get(&1u16)
+                .context("could not get client")?;
+            assert_eq!(client_one.available, "5".parse::<Money>()?);
+            assert_eq!(state.ledger.len(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parser_reads_multiple_inputs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(
+            path("day-2.csv"),
+            "type, client, tx, amount\nwithdrawal, 1, 2, 4\n",
+        )?;
+        std::fs::write(
+            path("day-1.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5\n",
+        )?;
+        std::fs::write(
+            path("extra.csv"),
+            "type, client, tx, amount\nwithdrawal, 1, 3, 4\n",
+        )?;
+
+        let names = input_names(&[path("day-*.csv"), path("extra.csv")])?;
+        assert_eq!(
+            names,
+            vec![path("day-1.csv"), path("day-2.csv"), path("extra.csv")]
+        );
+        assert!(input_names(&[path("none-*.csv")]).is_err());
+        assert!(
+            read_inputs(
+                &[path("none.csv")],
+                InputFormat::Csv,
+                ReadOptions::default()
+            )
+            .is_err()
+        );
+
+        let records = read_inputs(&names, InputFormat::Csv, ReadOptions::default())?;
+        let mut rejects = RejectLog::default().with_validation(Validation::Strict);
+        let err = handle_transactions(records, &EngineArgs::default(), &mut rejects)
+            .err()
+            .context("last withdrawal fails")?;
+        let reject = err
+            .downcast_ref::<Reject>()
+            .context("fails with a reject")?;
+        assert_eq!(reject.source.as_deref(), Some(path("extra.csv").as_str()));
+        assert_eq!(reject.line, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn parser_opens_inputs_one_at_a_time() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(
+            path("a.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5\n",
+        )?;
+        std::fs::write(
+            path("b.csv"),
+            "type, client, tx, amount\ndeposit, 1, 2, 5\n",
+        )?;
+
+        // Two locks on stdin would wait on each other forever
+        let stdin_twice = [STDIN.to_string(), STDIN.to_string()];
+        assert!(read_inputs(&stdin_twice, InputFormat::Csv, ReadOptions::default()).is_err());
+
+        // b.csv isn't open yet while a.csv is read, so it's only missed then
+        let mut records = read_inputs(
+            &[path("a.csv"), path("b.csv")],
+            InputFormat::Csv,
+            ReadOptions::default(),
+        )?;
+        let first = readable(records.next().context("a.csv has a record")?)?;
+        assert_eq!(first.source.as_deref(), Some(path("a.csv").as_str()));
+        std::fs::remove_file(path("b.csv"))?;
+
+        let err = readable(records.next().context("b.csv fails to open")?)
+            .err()
+            .context("b.csv can't be read")?;
+        assert!(
+            err.to_string()
+                .starts_with(&format!("could not read {}:", path("b.csv")))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parser_fails_on_truncated_archive() -> anyhow::Result<()> {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("trunc.csv.gz");
+        let mut csv = String::from("type, client, tx, amount\n");
+        for tx in 1..=20_000 {
+            csv.push_str(&format!("deposit, 1, {}, 1\n", tx));
+        }
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(csv.as_bytes())?;
+        let gzip = gzip.finish()?;
+        std::fs::write(&path, &gzip[..gzip.len() / 2])?;
+
+        // Not a malformed record to skip, nor a partial result
+        let names = [path.to_string_lossy().into_owned()];
+        for format in [InputFormat::Csv, InputFormat::Jsonl] {
+            let records = read_inputs(&names, format, ReadOptions::default())?;
+            assert!(
+                handle_transactions(records, &EngineArgs::default(), &mut RejectLog::default())
+                    .is_err()
+            );
+        }
+        let records = read_inputs(&names, InputFormat::Csv, ReadOptions::default())?;
+        let workers = NonZeroUsize::new(2).context("non-zero")?;
+        assert!(
+            handle_transactions_sharded(
+                records,
+                workers,
+                &EngineArgs::default(),
+                &mut RejectLog::default()
+            )
+            .is_err()
+        );
         Ok(())
     }
     // We can write way more tests here, I just don't have time
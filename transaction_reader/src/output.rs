@@ -0,0 +1,277 @@
+use transaction::{ClientAccount, Money};
+
+use clap::{Args, ValueEnum};
+use csv::WriterBuilder;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::io::Write;
+
+#[derive(Debug, Default, Args)]
+pub struct OutputArgs {
+    /// Format of the accounts written to stdout
+    #[arg(long, value_enum, default_value_t)]
+    pub output_format: OutputFormat,
+
+    /// Order accounts by this column, ties by client id
+    #[arg(long, value_enum, default_value_t)]
+    pub sort_by: SortKey,
+
+    /// Sort in descending order
+    #[arg(long)]
+    pub desc: bool,
+
+    // Adds a `fraud_flags` column, set when fraud rules run
+    #[arg(skip)]
+    pub fraud_flags: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // A single array of account objects
+    Json,
+    // One account object per line
+    Jsonl,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+// Accounts come out of the repositories in no particular order,
+// sorting them keeps the output stable between runs
+pub fn sort(accounts: &mut [ClientAccount], key: SortKey, descending: bool) {
+    accounts.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Client => a.client_id.cmp(&b.client_id),
+            SortKey::Available => a.available.cmp(&b.available),
+            SortKey::Held => a.held.cmp(&b.held),
+            SortKey::Total => a.total().cmp(&b.total()),
+        };
+        let order = if descending { order.reverse() } else { order };
+        order.then(a.client_id.cmp(&b.client_id))
+    });
+}
+
+// The `fraud_flags` column is left out unless fraud rules ran, so the
+// output stays as specified
+#[derive(Serialize)]
+struct CsvAccount {
+    client: u16,
+    available: Money,
+    held: Money,
+    total: Money,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fraud_flags: Option<u32>,
+}
+
+// Unlike the CSV output, amounts are JSON numbers. They're written from
+// their exact decimal text rather than going through a float.
+#[derive(Serialize)]
+struct JsonAccount {
+    client: u16,
+    available: Box<RawValue>,
+    held: Box<RawValue>,
+    total: Box<RawValue>,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fraud_flags: Option<u32>,
+}
+
+fn json_number(money: Money) -> anyhow::Result<Box<RawValue>> {
+    Ok(RawValue::from_string(money.to_string())?)
+}
+
+fn total(account: &ClientAccount) -> anyhow::Result<Money> {
+    account
+        .total()
+        .ok_or_else(|| anyhow::anyhow!("account {} total overflows", account.client_id))
+}
+
+impl CsvAccount {
+    fn new(account: &ClientAccount, fraud_flags: bool) -> anyhow::Result<Self> {
+        Ok(CsvAccount {
+            client: account.client_id,
+            available: account.available,
+            held: account.held,
+            total: total(account)?,
+            locked: account.locked,
+            fraud_flags: fraud_flags.then_some(account.fraud_flags()),
+        })
+    }
+}
+
+impl JsonAccount {
+    fn new(account: &ClientAccount, fraud_flags: bool) -> anyhow::Result<Self> {
+        Ok(JsonAccount {
+            client: account.client_id,
+            available: json_number(account.available)?,
+            held: json_number(account.held)?,
+            total: json_number(total(account)?)?,
+            locked: account.locked,
+            fraud_flags: fraud_flags.then_some(account.fraud_flags()),
+        })
+    }
+}
+
+pub fn write<W: Write>(
+    accounts: &[ClientAccount],
+    format: OutputFormat,
+    fraud_flags: bool,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = WriterBuilder::new().flexible(true).from_writer(writer);
+            for account in accounts {
+                writer.serialize(CsvAccount::new(account, fraud_flags)?)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            let accounts = accounts
+                .iter()
+                .map(|account| JsonAccount::new(account, fraud_flags))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            serde_json::to_writer(&mut writer, &accounts)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Jsonl => {
+            for account in accounts {
+                serde_json::to_writer(&mut writer, &JsonAccount::new(account, fraud_flags)?)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction::{FraudAction, FraudDecision};
+
+    fn written(format: OutputFormat) -> anyhow::Result<String> {
+        written_with(format, false)
+    }
+
+    fn written_with(format: OutputFormat, fraud_flags: bool) -> anyhow::Result<String> {
+        let mut account = ClientAccount::new(2);
+        account.available = "1.5".parse()?;
+        account.held = "12345678901234.0001".parse()?;
+        account.locked = true;
+        let decision = |transaction_id| FraudDecision {
+            rule: "withdrawal velocity".to_string(),
+            action: FraudAction::Flag,
+            client_id: 2,
+            transaction_id,
+        };
+        account.fraud_decisions = vec![decision(3), decision(5)];
+
+        let mut output = Vec::new();
+        write(
+            &[ClientAccount::new(1), account],
+            format,
+            fraud_flags,
+            &mut output,
+        )?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn sorts_accounts() -> anyhow::Result<()> {
+        let account = |client_id, available: &str, held: &str| -> anyhow::Result<ClientAccount> {
+            let mut account = ClientAccount::new(client_id);
+            account.available = available.parse()?;
+            account.held = held.parse()?;
+            Ok(account)
+        };
+        let mut accounts = vec![
+            account(3, "1", "5")?,
+            account(1, "2", "0")?,
+            account(2, "-1", "4")?,
+            account(4, "2", "1")?,
+        ];
+        let clients =
+            |accounts: &[ClientAccount]| accounts.iter().map(|a| a.client_id).collect::<Vec<_>>();
+
+        sort(&mut accounts, SortKey::Client, false);
+        assert_eq!(clients(&accounts), vec![1, 2, 3, 4]);
+
+        sort(&mut accounts, SortKey::Available, false);
+        assert_eq!(clients(&accounts), vec![2, 3, 1, 4]);
+
+        // Ties stay in client order either way
+        sort(&mut accounts, SortKey::Available, true);
+        assert_eq!(clients(&accounts), vec![1, 4, 3, 2]);
+
+        sort(&mut accounts, SortKey::Held, true);
+        assert_eq!(clients(&accounts), vec![3, 2, 4, 1]);
+
+        sort(&mut accounts, SortKey::Total, false);
+        assert_eq!(clients(&accounts), vec![1, 2, 4, 3]);
+
+        sort(&mut accounts, SortKey::Client, true);
+        assert_eq!(clients(&accounts), vec![4, 3, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_csv() -> anyhow::Result<()> {
+        assert_eq!(
+            written(OutputFormat::Csv)?,
+            "client,available,held,total,locked
+1,0.0000,0.0000,0.0000,false
+2,1.5000,12345678901234.0001,12345678901235.5001,true
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_exact_json_numbers() -> anyhow::Result<()> {
+        let json = written(OutputFormat::Json)?;
+        assert_eq!(
+            json,
+            r#"[{"client":1,"available":0.0000,"held":0.0000,"total":0.0000,"locked":false},{"client":2,"available":1.5000,"held":12345678901234.0001,"total":12345678901235.5001,"locked":true}]
+"#
+        );
+
+        let parsed: serde_json::Value = serde_json::from_str(&json)?;
+        assert!(parsed[1]["available"].is_number());
+
+        let jsonl = written(OutputFormat::Jsonl)?;
+        assert_eq!(jsonl.lines().count(), 2);
+        assert_eq!(
+            jsonl.lines().next(),
+            Some(r#"{"client":1,"available":0.0000,"held":0.0000,"total":0.0000,"locked":false}"#)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_fraud_flags_when_asked() -> anyhow::Result<()> {
+        assert_eq!(
+            written_with(OutputFormat::Csv, true)?,
+            "client,available,held,total,locked,fraud_flags
+1,0.0000,0.0000,0.0000,false,0
+2,1.5000,12345678901234.0001,12345678901235.5001,true,2
+"
+        );
+        assert_eq!(
+            written_with(OutputFormat::Jsonl, true)?.lines().next(),
+            Some(
+                r#"{"client":1,"available":0.0000,"held":0.0000,"total":0.0000,"locked":false,"fraud_flags":0}"#
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use transaction::{Reject, Validation};
+
+use csv::{Writer, WriterBuilder};
+use std::fs::File;
+use std::path::Path;
+
+// Writes rejects to the `--rejects` file, a CSV with the input and line
+// number, reason code and message, followed by the record's original fields.
+// Does nothing when no file was asked for.
+//
+// Under `--strict` the first reject reported ends the run.
+#[derive(Default)]
+pub struct RejectLog {
+    writer: Option<Writer<File>>,
+    validation: Validation,
+}
+
+impl RejectLog {
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut writer = WriterBuilder::new().flexible(true).from_path(path)?;
+        writer.write_record(["file", "line", "code", "message", "fields"])?;
+        Ok(RejectLog {
+            writer: Some(writer),
+            validation: Validation::Lenient,
+        })
+    }
+
+    pub fn with_validation(self, validation: Validation) -> Self {
+        RejectLog { validation, ..self }
+    }
+
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    // Records the reject, failing if it should stop the run
+    pub fn report(&mut self, reject: Reject) -> anyhow::Result<()> {
+        self.write(&reject)?;
+        match self.validation {
+            Validation::Lenient => {
+                // Assumption: ignore invalid and malformed transations
+                eprintln!("ignoring {}", reject);
+                Ok(())
+            }
+            Validation::Strict => {
+                self.finish()?;
+                Err(anyhow::Error::new(reject).context("stopped at the first invalid record"))
+            }
+        }
+    }
+
+    pub fn write(&mut self, reject: &Reject) -> anyhow::Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+
+        let line = reject.line.to_string();
+        let prefix = [
+            reject.source.as_deref().unwrap_or_default(),
+            line.as_str(),
+            reject.code,
+            reject.message.as_str(),
+        ];
+        writer.write_record(prefix.into_iter().chain(&reject.fields))?;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
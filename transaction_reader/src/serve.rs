@@ -0,0 +1,124 @@
+use transaction::SharedEngine;
+use transaction::stream::handle_csv;
+
+use futures::StreamExt;
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+// Pause after a failed accept, so running out of file descriptors
+// doesn't spin the loop while connections close
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Accepts connections until `shutdown` completes. Every connection sends
+// CSV lines, optionally starting with a header, and gets one reply line
+// per record saying whether it was accepted. Connections are handled
+// concurrently, each one's records in the order they were sent.
+pub async fn serve(
+    listener: TcpListener,
+    engine: SharedEngine,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<SharedEngine> {
+    let mut shutdown = pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                // Failing to accept one connection, say one that was reset
+                // before we got to it, doesn't stop the others
+                let (socket, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        eprintln!("could not accept a connection: {}", err);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(engine, socket).await {
+                        eprintln!("connection from {} failed: {}", peer, err);
+                    }
+                });
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    Ok(engine)
+}
+
+async fn handle_connection(engine: SharedEngine, socket: TcpStream) -> std::io::Result<()> {
+    let (reader, mut writer) = socket.into_split();
+
+    let mut outcomes = pin!(handle_csv(engine, reader));
+    while let Some(outcome) = outcomes.next().await {
+        writer
+            .write_all(format!("{}\n", outcome).as_bytes())
+            .await?;
+    }
+
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction::{Money, TransactionEngine};
+
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::sync::{Mutex, oneshot};
+
+    async fn send(address: std::net::SocketAddr, lines: &str) -> anyhow::Result<Vec<String>> {
+        let mut socket = TcpStream::connect(address).await?;
+        socket.write_all(lines.as_bytes()).await?;
+        socket.shutdown().await?;
+
+        let mut replies = Vec::new();
+        let mut reader = BufReader::new(socket).lines();
+        while let Some(line) = reader.next_line().await? {
+            replies.push(line);
+        }
+        Ok(replies)
+    }
+
+    #[tokio::test]
+    async fn serves_concurrent_clients() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let engine: SharedEngine = Arc::new(Mutex::new(TransactionEngine::default()));
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, engine, async {
+            let _ = stopped.await;
+        }));
+
+        let (first, second) = tokio::join!(
+            send(address, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n"),
+            send(address, "deposit, 2, 2, 1.0\nwithdrawal, 2, 3, 5.0\nnope\n"),
+        );
+        assert_eq!(first?, vec!["accepted Deposit 1"]);
+
+        let second = second?;
+        assert_eq!(second.len(), 3);
+        assert_eq!(second[0], "accepted Deposit 2");
+        assert!(second[1].starts_with("rejected Withdraw 3: "));
+        assert!(second[2].starts_with("malformed line 3: "));
+
+        let _ = stop.send(());
+        let engine = server.await??;
+        let engine = engine.lock().await;
+        assert_eq!(
+            engine.client_accounts[&1].available,
+            "2.0".parse::<Money>()?
+        );
+        assert_eq!(
+            engine.client_accounts[&2].available,
+            "1.0".parse::<Money>()?
+        );
+        Ok(())
+    }
+}